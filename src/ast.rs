@@ -1,10 +1,15 @@
-use std::boxed;
-
+use crate::diagnostic::Diagnostic;
+use crate::token::Span;
 use crate::token::Token;
 use crate::token::TokenType;
 use crate::token::Type;
-use std::fs;
-use std::io::Write;
+#[derive(Debug, Clone)]
+pub struct Parameter {
+    pub name: String,
+    pub var_type: Type,
+    pub span: Span,
+}
+
 #[derive(Debug, Clone)]
 pub enum ASTNode {
     //Enum with node types
@@ -12,155 +17,249 @@ pub enum ASTNode {
     VariableDeclaration {
         identifier: String,
         initializer: Option<Box<ASTNode>>,
-        line: u32,
+        span: Span,
         var_type: Option<Type>,
     },
-    DisplayStatement(String, u32),
-    DisplayIntStatement(String, u32),
-    DisplayStringStatement(String, u32),
-    DisplayStringVariable(String,u32),
+    DisplayStatement(String, Span),
+    DisplayIntStatement(String, Span),
+    DisplayStringStatement(String, Span),
+    DisplayStringVariable(String, Span),
+    DisplayBoolStatement(String, Span),
+    //ACCEPTnumeric / ACCEPTstring: read one line of input into a variable
+    AcceptIntStatement(String, Span),
+    AcceptStringStatement(String, Span),
     ExpressionStatement {
         expression: Box<ASTNode>,
         identifier: String,
-        line: u32,
+        span: Span,
     },
     BinaryOp {
         operator: String,
         left: Box<ASTNode>,
         right: Box<ASTNode>,
-        line: u32,
+        span: Span,
     },
-    Identifier(String, u32),
-    Number(String, u32),
-    StringLiteral(String, u32),
+    UnaryOp {
+        operator: String,
+        operand: Box<ASTNode>,
+        span: Span,
+    },
+    Identifier(String, Span),
+    Number(String, Span),
+    Float(String, Span),
+    StringLiteral(String, Span),
+    Boolean(bool, Span),
 
     IfStatement {
         condition: Box<ASTNode>,
         then_block: Vec<ASTNode>,
         else_if_blocks: Vec<(Box<ASTNode>, Vec<ASTNode>)>,
         else_block: Option<Vec<ASTNode>>,
-        line: u32,
+        span: Span,
+    },
+    WhileStatement {
+        condition: Box<ASTNode>,
+        body: Vec<ASTNode>,
+        span: Span,
+    },
+    ForStatement {
+        variable: String,
+        start: Box<ASTNode>,
+        end: Box<ASTNode>,
+        step: Option<Box<ASTNode>>,
+        body: Vec<ASTNode>,
+        span: Span,
+    },
+    FunctionDeclaration {
+        name: String,
+        params: Vec<Parameter>,
+        return_type: Option<Type>,
+        body: Vec<ASTNode>,
+        span: Span,
+    },
+    ReturnStatement {
+        value: Option<Box<ASTNode>>,
+        span: Span,
+    },
+    Call {
+        name: String,
+        args: Vec<ASTNode>,
+        span: Span,
+    },
+    CallStatement {
+        call: Box<ASTNode>,
+        span: Span,
     },
 }
 
 impl ASTNode {
-    pub fn traverse<F>(&self, f: &F)
-    where
-        F: Fn(&ASTNode),
-    {
-        f(self);
+    /// Source text the node was parsed from; `None` for the program root.
+    pub fn span(&self) -> Option<Span> {
         match self {
-            ASTNode::Program(statements) => {
-                for statement in statements {
-                    statement.traverse(f);
-                }
-            }
-            ASTNode::VariableDeclaration { initializer, .. } => {
-                if let Some(init) = initializer {
-                    init.traverse(f);
-                }
-            }
-            ASTNode::ExpressionStatement { expression, .. } => {
-                expression.traverse(f);
-            }
-            ASTNode::BinaryOp { left, right, .. } => {
-                left.traverse(f);
-                right.traverse(f);
-            }
-            ASTNode::IfStatement {
-                condition,
-                then_block,
-                else_if_blocks,
-                else_block,
-                line,
-            } => {
-                condition.traverse(f);
-                if then_block.is_empty() {
-                    println!("No then block")
-                } else {
-                    for x in then_block {
-                        x.traverse(f);
-                    }
-                }
-
-                if else_if_blocks.is_empty() {
-                    println!("No elif block")
-                } else {
-                    for (boxed_node, child_node) in else_if_blocks {
-                        boxed_node.traverse(f);
-
-                        for x in child_node {
-                            x.traverse(f);
-                        }
-                    }
-                }
-
-                match else_block {
-                    Some(elb) => {
-                        for x in elb {
-                            x.traverse(f);
-                        }
-                    }
-                    None => {
-                        // TODO: Implement specific behavior for when there's no else block
-                        // For now, do nothing
-                    }
-                }
-            }
+            ASTNode::Program(_) => None,
+            ASTNode::VariableDeclaration { span, .. }
+            | ASTNode::ExpressionStatement { span, .. }
+            | ASTNode::BinaryOp { span, .. }
+            | ASTNode::UnaryOp { span, .. }
+            | ASTNode::IfStatement { span, .. }
+            | ASTNode::WhileStatement { span, .. }
+            | ASTNode::ForStatement { span, .. }
+            | ASTNode::FunctionDeclaration { span, .. }
+            | ASTNode::ReturnStatement { span, .. }
+            | ASTNode::Call { span, .. }
+            | ASTNode::CallStatement { span, .. } => Some(*span),
+            ASTNode::DisplayStatement(_, span)
+            | ASTNode::DisplayIntStatement(_, span)
+            | ASTNode::DisplayStringStatement(_, span)
+            | ASTNode::DisplayStringVariable(_, span)
+            | ASTNode::DisplayBoolStatement(_, span)
+            | ASTNode::AcceptIntStatement(_, span)
+            | ASTNode::AcceptStringStatement(_, span)
+            | ASTNode::Identifier(_, span)
+            | ASTNode::Number(_, span)
+            | ASTNode::Float(_, span)
+            | ASTNode::StringLiteral(_, span)
+            | ASTNode::Boolean(_, span) => Some(*span),
+        }
+    }
 
-            _ => {}
+    /// Value of a minus sign applied straight to a literal. This is the only way to
+    /// write i64::MIN, whose digits alone don't fit in an i64.
+    pub fn negated_literal(&self) -> Option<i64> {
+        match self {
+            ASTNode::UnaryOp {
+                operator, operand, ..
+            } if operator == "-" => match operand.as_ref() {
+                ASTNode::Number(digits, _) => format!("-{}", digits).parse().ok(),
+                _ => None,
+            },
+            _ => None,
         }
     }
 }
+fn node_span(node: &ASTNode) -> Span {
+    node.span().unwrap_or_default()
+}
+
 pub struct Parser {
     tokens: Vec<Token>,
     current: usize,
-    nodehold: Vec<ASTNode>,
+    diagnostics: Vec<Diagnostic>,
+    missing_end: bool,
+    block_depth: usize,
 }
 
 impl Parser {
-    pub fn new(tokens: Vec<Token>) -> Self {
+    pub fn new(mut tokens: Vec<Token>) -> Self {
+        tokens.retain(|t| t.token_type != TokenType::Comment);
+        //Programs must finish with END; add a stand-in so the parser never runs off the end.
+        //With no tokens at all it goes where the file starts
+        let missing_end = !tokens.iter().any(|t| t.token_type == TokenType::EOF);
+        if missing_end {
+            let start = Span {
+                line: 1,
+                column: 1,
+                ..Span::default()
+            };
+            let end = tokens.last().map_or(start, |t| t.span.after());
+            tokens.push(Token::newtok(TokenType::EOF, String::new(), None, end, None));
+        }
         Parser {
             tokens,
             current: 0,
-            nodehold: Vec::new(),
+            diagnostics: Vec::new(),
+            missing_end,
+            block_depth: 0,
         }
     }
 
-    pub fn parse(&mut self) -> Result<ASTNode, String> {
-        self.program()
+    /// Parses the whole program. Syntax errors do not stop the parse: the returned
+    /// AST holds every statement that parsed cleanly, alongside all diagnostics.
+    pub fn parse(&mut self) -> (ASTNode, Vec<Diagnostic>) {
+        let program = self.program();
+        (program, std::mem::take(&mut self.diagnostics))
     }
 
-    fn program(&mut self) -> Result<ASTNode, String> {
+    fn program(&mut self) -> ASTNode {
         let mut statements = Vec::new();
         while !self.is_at_end() {
-            let stmt = self.statement()?;
-            self.nodehold.push(stmt.clone()); // Add this line to store in nodehold
-            statements.push(stmt);
+            if self.at_block_end() {
+                let stray = self.advance();
+                let opener = match stray.token_type {
+                    TokenType::EndWhile => "WHILE",
+                    TokenType::EndFor => "FOR",
+                    TokenType::EndFunc => "FUNC",
+                    _ => "IF",
+                };
+                self.diagnostics.push(Diagnostic::error(
+                    "E0103",
+                    format!("'{}' without a matching {}", stray.lexeme, opener),
+                    stray.span,
+                ));
+                continue;
+            }
+            if let Some(stmt) = self.recovering_statement() {
+                statements.push(stmt);
+            }
+        }
+        if self.missing_end {
+            self.diagnostics.push(Diagnostic::error(
+                "E0104",
+                "Expected 'END' at the end of the program",
+                self.peek().span,
+            ));
         }
-        Ok(ASTNode::Program(statements))
+        ASTNode::Program(statements)
     }
 
-    fn statement(&mut self) -> Result<ASTNode, String> {
+    fn statement(&mut self) -> Result<ASTNode, Diagnostic> {
         //Basically matches on current token without consume. Potentially compress peek into the ad
         if self.match_token(&[TokenType::IntVar]) {
-            self.IntVariable_declaration()
+            self.variable_declaration(Type::Int)
         } else if self.match_token(&[TokenType::StrVar]) {
-            self.StrVariable_declaration()
+            self.variable_declaration(Type::Str)
+        } else if self.match_token(&[TokenType::BoolVar]) {
+            self.variable_declaration(Type::Bool)
+        } else if self.match_token(&[TokenType::FloatVar]) {
+            self.variable_declaration(Type::Float)
         } else if self.match_token(&[TokenType::Display]) {
             self.display_statement()
         } else if self.match_token(&[TokenType::DisplayInt]) {
             self.display_int()
         } else if self.match_token(&[TokenType::DisplayStr]) {
             self.display_string()
+        } else if self.match_token(&[TokenType::DisplayBool]) {
+            self.display_bool()
+        } else if self.match_token(&[TokenType::AcceptInt]) {
+            self.accept_statement(ASTNode::AcceptIntStatement)
+        } else if self.match_token(&[TokenType::AcceptStr]) {
+            self.accept_statement(ASTNode::AcceptStringStatement)
         } else if self.match_token(&[TokenType::If]) {
             self.if_block()
+        } else if self.match_token(&[TokenType::While]) {
+            self.while_block()
+        } else if self.match_token(&[TokenType::For]) {
+            self.for_block()
+        } else if self.match_token(&[TokenType::Func]) {
+            let function = self.function_declaration()?;
+            if self.block_depth > 0 {
+                return Err(Diagnostic::error(
+                    "E0105",
+                    "FUNC can only be declared at the top level",
+                    node_span(&function),
+                ));
+            }
+            Ok(function)
+        } else if self.match_token(&[TokenType::Return]) {
+            self.return_statement()
+        } else if self.check(&TokenType::Identifier) && self.check_next(&TokenType::LParen) {
+            self.call_statement()
         } else {
             self.expression_statement()
         }
     }
 
-    fn IntVariable_declaration(&mut self) -> Result<ASTNode, String> {
+    fn variable_declaration(&mut self, var_type: Type) -> Result<ASTNode, Diagnostic> {
         let var_token = self.previous().clone();
         let identifier = self.consume(&TokenType::Identifier, "Expected identifier.")?;
 
@@ -178,35 +277,12 @@ impl Parser {
         Ok(ASTNode::VariableDeclaration {
             identifier: identifier.lexeme.clone(),
             initializer,
-            var_type: Some(Type::Int),
-            line: var_token.line,
+            var_type: Some(var_type),
+            span: var_token.span.to(self.previous().span),
         })
     }
 
-    fn StrVariable_declaration(&mut self) -> Result<ASTNode, String> {
-        let var_token = self.previous().clone();
-        let identifier = self.consume(&TokenType::Identifier, "Expected identifier.")?;
-
-        let initializer = if self.match_token(&[TokenType::Equals]) {
-            Some(Box::new(self.expression()?))
-        } else {
-            None
-        };
-
-        self.consume(
-            &TokenType::Semicolon,
-            "Expected ';' after variable declaration.",
-        )?;
-
-        Ok(ASTNode::VariableDeclaration {
-            identifier: identifier.lexeme.clone(),
-            initializer,
-            var_type: Some(Type::Str),
-            line: var_token.line,
-        })
-    }
-
-    fn display_statement(&mut self) -> Result<ASTNode, String> {
+    fn display_statement(&mut self) -> Result<ASTNode, Diagnostic> {
         let display_token = self.previous().clone();
         let identifier = self.consume(
             &TokenType::Identifier,
@@ -218,11 +294,11 @@ impl Parser {
         )?;
         Ok(ASTNode::DisplayStatement(
             identifier.lexeme.clone(),
-            display_token.line,
+            display_token.span.to(self.previous().span),
         ))
     }
 
-    fn display_int(&mut self) -> Result<ASTNode, String> {
+    fn display_int(&mut self) -> Result<ASTNode, Diagnostic> {
         let display_token = self.previous().clone();
         let identifier = self.consume(&TokenType::Identifier, "Expected identifier before int.")?;
         self.consume(
@@ -231,11 +307,11 @@ impl Parser {
         )?;
         Ok(ASTNode::DisplayIntStatement(
             identifier.lexeme.clone(),
-            display_token.line,
+            display_token.span.to(self.previous().span),
         ))
     }
 
-    fn display_string(&mut self) -> Result<ASTNode, String> {
+    fn display_string(&mut self) -> Result<ASTNode, Diagnostic> {
         let display_token = self.previous().clone();
 
         // Check if next token is an identifier or string literal
@@ -247,7 +323,7 @@ impl Parser {
             )?;
             Ok(ASTNode::DisplayStringVariable(
                 identifier.lexeme.clone(),
-                display_token.line,
+                display_token.span.to(self.previous().span),
             ))
         } else if self.check(&TokenType::String) {
             let string_literal = self.advance();
@@ -257,116 +333,308 @@ impl Parser {
             )?;
             Ok(ASTNode::DisplayStringStatement(
                 string_literal.lexeme.clone(),
-                display_token.line,
+                display_token.span.to(self.previous().span),
             ))
         } else {
-            Err(format!(
-                "Expected identifier or string literal after DISPLAYstring at line {}.",
-                self.peek().line
+            Err(Diagnostic::error(
+                "E0102",
+                "Expected identifier or string literal after DISPLAYstring.",
+                self.peek().span,
             ))
         }
     }
-    fn expression_statement(&mut self) -> Result<ASTNode, String> {
+    fn display_bool(&mut self) -> Result<ASTNode, Diagnostic> {
+        let display_token = self.previous().clone();
+        let identifier = self.consume(&TokenType::Identifier, "Expected identifier after DISPLAYbool.")?;
+        self.consume(
+            &TokenType::Semicolon,
+            "Expected ';' after display statement.",
+        )?;
+        Ok(ASTNode::DisplayBoolStatement(
+            identifier.lexeme.clone(),
+            display_token.span.to(self.previous().span),
+        ))
+    }
+
+    /// `ACCEPTnumeric x;` or `ACCEPTstring s;`; `node` builds the statement.
+    fn accept_statement(
+        &mut self,
+        node: fn(String, Span) -> ASTNode,
+    ) -> Result<ASTNode, Diagnostic> {
+        let accept_token = self.previous().clone();
+        let identifier = self.consume(
+            &TokenType::Identifier,
+            &format!("Expected identifier after {}.", accept_token.lexeme),
+        )?;
+        self.consume(
+            &TokenType::Semicolon,
+            "Expected ';' after accept statement.",
+        )?;
+        Ok(node(
+            identifier.lexeme,
+            accept_token.span.to(self.previous().span),
+        ))
+    }
+
+    fn expression_statement(&mut self) -> Result<ASTNode, Diagnostic> {
         let identifier = self.consume(&TokenType::Identifier, "Expected identifier before exp.")?;
-        let equals_token = self.consume(&TokenType::Equals, "Expected '='.")?;
-        let expr = self.expression()?;
+        let compound = self.match_token(&[
+            TokenType::PlusEqual,
+            TokenType::MinusEqual,
+            TokenType::StarEqual,
+            TokenType::SlashEqual,
+            TokenType::PercentEqual,
+        ]);
+        let assign = if compound {
+            self.previous()
+        } else {
+            self.consume(&TokenType::Equals, "Expected '='.")?
+        };
+        let mut expr = self.expression()?;
         self.consume(
             &TokenType::Semicolon,
             "Expected ';' after expression statement.",
         )?;
+        // x += e is plain assignment from here on: x = x + (e)
+        if compound {
+            let span = identifier.span.to(node_span(&expr));
+            expr = ASTNode::BinaryOp {
+                operator: assign.lexeme.trim_end_matches('=').to_string(),
+                left: Box::new(ASTNode::Identifier(
+                    identifier.lexeme.clone(),
+                    identifier.span,
+                )),
+                right: Box::new(expr),
+                span,
+            };
+        }
         Ok(ASTNode::ExpressionStatement {
             expression: Box::new(expr),
             identifier: identifier.lexeme.clone(),
-            line: equals_token.line,
+            span: identifier.span.to(self.previous().span),
         })
     }
 
-    fn expression(&mut self) -> Result<ASTNode, String> {
-        self.term()
+    fn return_statement(&mut self) -> Result<ASTNode, Diagnostic> {
+        let return_token = self.previous().clone();
+        let value = if self.check(&TokenType::Semicolon) {
+            None
+        } else {
+            Some(Box::new(self.expression()?))
+        };
+        self.consume(&TokenType::Semicolon, "Expected ';' after RETURN.")?;
+        Ok(ASTNode::ReturnStatement {
+            value,
+            span: return_token.span.to(self.previous().span),
+        })
+    }
+
+    fn call_statement(&mut self) -> Result<ASTNode, Diagnostic> {
+        let name = self.advance();
+        let call = self.call(name)?;
+        self.consume(&TokenType::Semicolon, "Expected ';' after call.")?;
+        let span = node_span(&call).to(self.previous().span);
+        Ok(ASTNode::CallStatement {
+            call: Box::new(call),
+            span,
+        })
+    }
+
+    fn call(&mut self, name: Token) -> Result<ASTNode, Diagnostic> {
+        //The function name has been consumed; parse `(arg, ...)`
+        self.consume(&TokenType::LParen, "Expected '(' after function name.")?;
+        let mut args = Vec::new();
+        if !self.check(&TokenType::RParen) {
+            loop {
+                args.push(self.expression()?);
+                if !self.match_token(&[TokenType::Comma]) {
+                    break;
+                }
+            }
+        }
+        self.consume(&TokenType::RParen, "Expected ')' after arguments.")?;
+        Ok(ASTNode::Call {
+            name: name.lexeme,
+            args,
+            span: name.span.to(self.previous().span),
+        })
     }
 
-    fn term(&mut self) -> Result<ASTNode, String> {
-        let mut expr = self.factor()?;
+    fn expression(&mut self) -> Result<ASTNode, Diagnostic> {
+        self.or()
+    }
 
-        while self.match_token(&[
-            TokenType::Plus,
-            TokenType::Minus,
-            TokenType::Star,
-            TokenType::Slash,
-            TokenType::LessThan,
-            TokenType::LessThanOrEqual,
-            TokenType::GreaterThan,
-            TokenType::GreaterThanOrEqual,
-        ]) {
+    //Logical operators bind loosest: OR, then AND, then NOT, then the binary operators in precedence()
+    fn or(&mut self) -> Result<ASTNode, Diagnostic> {
+        let mut expr = self.and()?;
+        while self.match_token(&[TokenType::Or]) {
             let operator = self.previous().clone();
-            let right = self.factor()?;
-            expr = ASTNode::BinaryOp {
-                operator: operator.lexeme,
-                left: Box::new(expr),
-                right: Box::new(right),
-                line: operator.line,
-            };
+            let right = self.and()?;
+            expr = Self::binary(operator, expr, right);
+        }
+        Ok(expr)
+    }
+
+    fn and(&mut self) -> Result<ASTNode, Diagnostic> {
+        let mut expr = self.not()?;
+        while self.match_token(&[TokenType::And]) {
+            let operator = self.previous().clone();
+            let right = self.not()?;
+            expr = Self::binary(operator, expr, right);
+        }
+        Ok(expr)
+    }
+
+    fn not(&mut self) -> Result<ASTNode, Diagnostic> {
+        if self.match_token(&[TokenType::Not]) {
+            let operator = self.previous().clone();
+            let operand = self.not()?;
+            return Ok(Self::unary(operator, operand));
+        }
+        self.binary_expression(1)
+    }
+
+    fn unary(operator: Token, operand: ASTNode) -> ASTNode {
+        let span = operator.span.to(node_span(&operand));
+        ASTNode::UnaryOp {
+            operator: operator.lexeme,
+            operand: Box::new(operand),
+            span,
+        }
+    }
+
+    fn binary(operator: Token, left: ASTNode, right: ASTNode) -> ASTNode {
+        let span = node_span(&left).to(node_span(&right));
+        ASTNode::BinaryOp {
+            operator: operator.lexeme,
+            left: Box::new(left),
+            right: Box::new(right),
+            span,
+        }
+    }
+
+    /// Binding power of the binary operators below NOT, loosest first.
+    fn precedence(token_type: &TokenType) -> Option<u8> {
+        match token_type {
+            TokenType::EqualEqual | TokenType::NotEqual => Some(1),
+            TokenType::LessThan
+            | TokenType::LessThanOrEqual
+            | TokenType::GreaterThan
+            | TokenType::GreaterThanOrEqual => Some(2),
+            TokenType::Plus | TokenType::Minus => Some(3),
+            TokenType::Star | TokenType::Slash | TokenType::Percent => Some(4),
+            _ => None,
+        }
+    }
+
+    // Precedence climbing: takes every operator binding at least as tightly as `min`.
+    // The right operand only takes tighter ones, so each level is left-associative
+    fn binary_expression(&mut self, min: u8) -> Result<ASTNode, Diagnostic> {
+        let mut expr = self.negation()?;
+
+        while let Some(precedence) =
+            Self::precedence(&self.peek().token_type).filter(|&p| p >= min)
+        {
+            let operator = self.advance();
+            let right = self.binary_expression(precedence + 1)?;
+            expr = Self::binary(operator, expr, right);
         }
 
         Ok(expr)
     }
 
-    fn factor(&mut self) -> Result<ASTNode, String> {
+    // Unary minus binds tighter than the operators in precedence(): -a * b is (-a) * b
+    fn negation(&mut self) -> Result<ASTNode, Diagnostic> {
+        if self.match_token(&[TokenType::Minus]) {
+            let operator = self.previous();
+            let operand = self.negation()?;
+            return Ok(Self::unary(operator, operand));
+        }
+        self.power()
+    }
+
+    // ** binds tighter still and groups to the right: -a ** b is -(a ** b), and
+    // a ** b ** c is a ** (b ** c). Its exponent may carry its own minus sign
+    fn power(&mut self) -> Result<ASTNode, Diagnostic> {
+        let base = self.factor()?;
+        if self.match_token(&[TokenType::StarStar]) {
+            let operator = self.previous();
+            let exponent = self.negation()?;
+            return Ok(Self::binary(operator, base, exponent));
+        }
+        Ok(base)
+    }
+
+    fn factor(&mut self) -> Result<ASTNode, Diagnostic> {
         if self.match_token(&[TokenType::Number]) {
             let token = self.previous().clone();
-            Ok(ASTNode::Number(token.lexeme, token.line))
+            Ok(ASTNode::Number(token.lexeme, token.span))
+        } else if self.match_token(&[TokenType::Float]) {
+            let token = self.previous().clone();
+            Ok(ASTNode::Float(token.lexeme, token.span))
         } else if self.match_token(&[TokenType::Identifier]) {
             let token = self.previous().clone();
-            Ok(ASTNode::Identifier(token.lexeme, token.line))
+            if self.check(&TokenType::LParen) {
+                return self.call(token);
+            }
+            Ok(ASTNode::Identifier(token.lexeme, token.span))
         } else if self.match_token(&[TokenType::String]) {
             let token = self.previous().clone();
-            Ok(ASTNode::StringLiteral(token.lexeme, token.line))
+            Ok(ASTNode::StringLiteral(token.lexeme, token.span))
+        } else if self.match_token(&[TokenType::True, TokenType::False]) {
+            let token = self.previous().clone();
+            Ok(ASTNode::Boolean(token.token_type == TokenType::True, token.span))
         } else if self.match_token(&[TokenType::LParen]) {
             let expr = self.expression()?;
             self.consume(&TokenType::RParen, "Expected ')' after expression.")?;
             Ok(expr)
         } else {
-            Err(format!(
-                "Unexpected token in factor at line {}.",
-                self.peek().line
+            Err(Diagnostic::error(
+                "E0101",
+                format!("Expected expression, found '{}'.", self.peek().lexeme),
+                self.peek().span,
             ))
         }
     }
 
-    fn if_block(&mut self) -> Result<ASTNode, String> {
+    fn if_block(&mut self) -> Result<ASTNode, Diagnostic> {
         //if statement generation
         let iftok = self.previous().clone();
 
-        self.consume(&TokenType::LParen, "Expected '(' after IF")?;
-
-        // Parse the condition
-        let cond = self.expression()?;
-
-        // Consume the right parenthesis
-        self.consume(&TokenType::RParen, "Expected ')' after condition")?;
-        //self.consume(&TokenType::LParen, "Expected '(' after IF")?;
-        //then in between these two tokens,the expression
-        //self.consume(&TokenType::RParen, "Expected ) after expression (ifblock)")?; //Ifblock version getting triggered
-
-        // Parse condition
-
-        self.consume(&TokenType::Then, "Expected 'THEN' after IF condition")?;
+        // A broken header is remembered rather than returned straight away, so the
+        // branches are still parsed and the parser comes out right after END-IF
+        let mut header_error = None;
+        let cond = match self.if_condition("IF") {
+            Ok(cond) => cond,
+            Err(diagnostic) => {
+                header_error = Some(diagnostic);
+                self.skip_past(&TokenType::Then);
+                ASTNode::Number("0".to_string(), iftok.span)
+            }
+        };
 
         // Parse then block
-        let i_block = self.block()?;
+        let i_block = self.block();
 
         // Parse optional else-if blocks
         let mut else_if_blocks = Vec::new();
         while self.match_token(&[TokenType::Elif]) {
-            let else_if_cond = self.factor()?;
-            self.consume(&TokenType::Then, "Expected 'THEN' after ELSE-IF condition")?;
-            let else_if_block = self.block()?;
+            let keyword = self.previous().lexeme;
+            let else_if_cond = match self.if_condition(&keyword) {
+                Ok(cond) => cond,
+                Err(diagnostic) => {
+                    self.diagnostics.push(diagnostic);
+                    self.skip_past(&TokenType::Then);
+                    ASTNode::Number("0".to_string(), self.previous().span)
+                }
+            };
+            let else_if_block = self.block();
             else_if_blocks.push((Box::new(else_if_cond), else_if_block));
         }
 
         // Parse optional else block
         let el_block = if self.match_token(&[TokenType::Else]) {
-            Some(self.block()?)
+            Some(self.block())
         } else {
             None
         };
@@ -376,28 +644,219 @@ impl Parser {
             &TokenType::Endifelseblock,
             "Expected 'END-IF' to close block",
         )?;
+        if let Some(diagnostic) = header_error {
+            return Err(diagnostic);
+        }
 
         Ok(ASTNode::IfStatement {
             condition: Box::new(cond),
             then_block: i_block,
             else_if_blocks,
             else_block: el_block,
-            line: iftok.line,
+            span: iftok.span.to(self.previous().span),
         })
     }
 
-    fn block(&mut self) -> Result<Vec<ASTNode>, String> {
+    /// `( expression ) THEN` after IF, ELSE-IF or ELIF (`keyword`).
+    fn if_condition(&mut self, keyword: &str) -> Result<ASTNode, Diagnostic> {
+        self.consume(&TokenType::LParen, &format!("Expected '(' after {}", keyword))?;
+
+        // Parse the condition
+        let cond = self.expression()?;
+
+        // Consume the right parenthesis
+        self.consume(&TokenType::RParen, "Expected ')' after condition")?;
+        //self.consume(&TokenType::LParen, "Expected '(' after IF")?;
+        //then in between these two tokens,the expression
+        //self.consume(&TokenType::RParen, "Expected ) after expression (ifblock)")?; //Ifblock version getting triggered
+
+        self.consume(
+            &TokenType::Then,
+            &format!("Expected 'THEN' after {} condition", keyword),
+        )?;
+        Ok(cond)
+    }
+
+    fn while_block(&mut self) -> Result<ASTNode, Diagnostic> {
+        let whiletok = self.previous().clone();
+
+        //Same recovery as if_block: a broken header still lets the body and END-WHILE parse
+        let header = self.loop_condition();
+        let cond = match &header {
+            Ok(cond) => cond.clone(),
+            Err(_) => {
+                self.skip_past(&TokenType::Do);
+                ASTNode::Number("0".to_string(), whiletok.span)
+            }
+        };
+
+        let body = self.block();
+
+        self.consume(&TokenType::EndWhile, "Expected 'END-WHILE' to close loop")?;
+        header?;
+
+        Ok(ASTNode::WhileStatement {
+            condition: Box::new(cond),
+            body,
+            span: whiletok.span.to(self.previous().span),
+        })
+    }
+
+    fn loop_condition(&mut self) -> Result<ASTNode, Diagnostic> {
+        self.consume(&TokenType::LParen, "Expected '(' after WHILE")?;
+        let cond = self.expression()?;
+        self.consume(&TokenType::RParen, "Expected ')' after condition")?;
+        self.consume(&TokenType::Do, "Expected 'DO' after WHILE condition")?;
+        Ok(cond)
+    }
+
+    fn for_block(&mut self) -> Result<ASTNode, Diagnostic> {
+        let fortok = self.previous().clone();
+
+        let header = self.for_header();
+        if header.is_err() {
+            //The header has no closing keyword, so resume at the first body statement
+            while !self.at_statement_boundary() {
+                self.advance();
+            }
+        }
+
+        let body = self.block();
+
+        self.consume(&TokenType::EndFor, "Expected 'END-FOR' to close loop")?;
+        let (variable, start, end, step) = header?;
+
+        Ok(ASTNode::ForStatement {
+            variable,
+            start: Box::new(start),
+            end: Box::new(end),
+            step: step.map(Box::new),
+            body,
+            span: fortok.span.to(self.previous().span),
+        })
+    }
+
+    fn for_header(&mut self) -> Result<(String, ASTNode, ASTNode, Option<ASTNode>), Diagnostic> {
+        let variable = self.consume(&TokenType::Identifier, "Expected loop variable after FOR")?;
+        self.consume(&TokenType::Equals, "Expected '=' after loop variable")?;
+        let start = self.expression()?;
+        self.consume(&TokenType::To, "Expected 'TO' after FOR start value")?;
+        let end = self.expression()?;
+        let step = if self.match_token(&[TokenType::Step]) {
+            Some(self.expression()?)
+        } else {
+            None
+        };
+        Ok((variable.lexeme, start, end, step))
+    }
+
+    fn function_declaration(&mut self) -> Result<ASTNode, Diagnostic> {
+        let functok = self.previous().clone();
+
+        let header = self.function_header();
+        if header.is_err() {
+            while !self.at_statement_boundary() {
+                self.advance();
+            }
+        }
+
+        let body = self.block();
+
+        self.consume(&TokenType::EndFunc, "Expected 'END-FUNC' to close function")?;
+        let (name, params, return_type) = header?;
+
+        Ok(ASTNode::FunctionDeclaration {
+            name,
+            params,
+            return_type,
+            body,
+            span: functok.span.to(self.previous().span),
+        })
+    }
+
+    fn function_header(&mut self) -> Result<(String, Vec<Parameter>, Option<Type>), Diagnostic> {
+        let name = self.consume(&TokenType::Identifier, "Expected function name after FUNC")?;
+        self.consume(&TokenType::LParen, "Expected '(' after function name")?;
+        let mut params = Vec::new();
+        if !self.check(&TokenType::RParen) {
+            loop {
+                params.push(self.parameter()?);
+                if !self.match_token(&[TokenType::Comma]) {
+                    break;
+                }
+            }
+        }
+        self.consume(&TokenType::RParen, "Expected ')' after parameters")?;
+
+        let return_type = if self.match_token(&[TokenType::Returns]) {
+            let type_name = self.consume(&TokenType::Identifier, "Expected type after RETURNS")?;
+            match type_name.var_type {
+                Some(var_type) => Some(var_type),
+                None => {
+                    return Err(Diagnostic::error(
+                        "E0106",
+                        format!("Unknown type '{}', expected int, float, str or bool", type_name.lexeme),
+                        type_name.span,
+                    ))
+                }
+            }
+        } else {
+            None
+        };
+        Ok((name.lexeme, params, return_type))
+    }
+
+    fn parameter(&mut self) -> Result<Parameter, Diagnostic> {
+        let var_type = if self.match_token(&[TokenType::IntVar]) {
+            Type::Int
+        } else if self.match_token(&[TokenType::StrVar]) {
+            Type::Str
+        } else if self.match_token(&[TokenType::BoolVar]) {
+            Type::Bool
+        } else if self.match_token(&[TokenType::FloatVar]) {
+            Type::Float
+        } else {
+            return Err(Diagnostic::error(
+                "E0100",
+                "Expected VARint, VARfloat, VARstr or VARbool parameter",
+                self.error_span(),
+            ));
+        };
+        let var_token = self.previous();
+        let name = self.consume(&TokenType::Identifier, "Expected parameter name")?;
+        Ok(Parameter {
+            name: name.lexeme,
+            var_type,
+            span: var_token.span.to(name.span),
+        })
+    }
+
+    fn block(&mut self) -> Vec<ASTNode> {
         let mut statements = Vec::new();
 
-        while !self.check(&TokenType::Elif)
-            && !self.check(&TokenType::Else)
-            && !self.check(&TokenType::Endifelseblock)
-        {
-            let statement = self.statement()?;
-            statements.push(statement);
+        self.block_depth += 1;
+        while !self.is_at_end() && !self.at_block_end() {
+            if let Some(statement) = self.recovering_statement() {
+                statements.push(statement);
+            }
         }
+        self.block_depth -= 1;
+
+        statements
+    }
 
-        Ok(statements)
+    /// Parses one statement. On a syntax error the diagnostic is recorded and the
+    /// parser skips ahead to the next statement boundary instead of giving up.
+    fn recovering_statement(&mut self) -> Option<ASTNode> {
+        let start = self.current;
+        match self.statement() {
+            Ok(statement) => Some(statement),
+            Err(diagnostic) => {
+                self.diagnostics.push(diagnostic);
+                self.synchronize(start);
+                None
+            }
+        }
     }
 
     fn match_token(&mut self, types: &[TokenType]) -> bool {
@@ -417,6 +876,12 @@ impl Parser {
         &self.peek().token_type == token_type
     }
 
+    fn check_next(&self, token_type: &TokenType) -> bool {
+        self.tokens
+            .get(self.current + 1)
+            .is_some_and(|t| &t.token_type == token_type)
+    }
+
     fn advance(&mut self) -> Token {
         if !self.is_at_end() {
             self.current += 1;
@@ -424,23 +889,27 @@ impl Parser {
         self.previous()
     }
 
-    fn consume(&mut self, token_type: &TokenType, message: &str) -> Result<Token, String> {
+    fn consume(&mut self, token_type: &TokenType, message: &str) -> Result<Token, Diagnostic> {
         //Eat the token
         if self.check(token_type) {
             //If it's legit,keep going
             Ok(self.advance())
         } else {
-            println!("Current AST contents dumped to file"); //dumps AST on error for
-            match fs::File::create("ASTdump.txt") {
-                Ok(mut file) => {
-                    if let Err(e) = writeln!(file, "{:?}", self.nodehold) {
-                        eprintln!("Failed to write to file: {}", e);
-                    }
-                }
-                Err(e) => eprintln!("Failed to create file: {}", e),
+            Err(Diagnostic::error("E0100", message, self.error_span()))
+        }
+    }
+
+    fn error_span(&self) -> Span {
+        //A token missing at the end of a line (usually ';') is reported right after
+        //the last token on that line instead of at the start of the next statement
+        let next = self.peek().span;
+        if self.current > 0 {
+            let last = self.previous().span;
+            if next.line > last.line {
+                return last.after();
             }
-            Err(format!("{} at line {}.", message, self.peek().line)) //Dump AST contents here
         }
+        next
     }
 
     fn is_at_end(&self) -> bool {
@@ -457,21 +926,252 @@ impl Parser {
         self.tokens[self.current - 1].clone()
     }
 
-    fn synchronize(&mut self) {
+    /// Tokens that close (part of) a block: the enclosing construct consumes them.
+    fn at_block_end(&self) -> bool {
+        matches!(
+            self.peek().token_type,
+            TokenType::Elif
+                | TokenType::Else
+                | TokenType::Endifelseblock
+                | TokenType::EndWhile
+                | TokenType::EndFor
+                | TokenType::EndFunc
+        )
+    }
+
+    fn at_statement_boundary(&self) -> bool {
+        matches!(
+            self.peek().token_type,
+            TokenType::IntVar
+                | TokenType::StrVar
+                | TokenType::BoolVar
+                | TokenType::FloatVar
+                | TokenType::Display
+                | TokenType::DisplayInt
+                | TokenType::DisplayStr
+                | TokenType::DisplayBool
+                | TokenType::AcceptInt
+                | TokenType::AcceptStr
+                | TokenType::If
+                | TokenType::While
+                | TokenType::For
+                | TokenType::Func
+                | TokenType::Return
+                | TokenType::Elif
+                | TokenType::Else
+                | TokenType::Endifelseblock
+                | TokenType::EndWhile
+                | TokenType::EndFor
+                | TokenType::EndFunc
+                | TokenType::EOF
+        )
+    }
+
+    fn skip_past(&mut self, token_type: &TokenType) {
+        while !self.at_statement_boundary() {
+            if self.advance().token_type == *token_type {
+                return;
+            }
+        }
+    }
+
+    fn synchronize(&mut self, start: usize) {
         //Synchronization function for error handling
-        self.advance();
+        if self.current == start {
+            //Nothing was consumed, so step over the offending token to guarantee progress
+            self.advance();
+        }
 
         while !self.is_at_end() {
-            if let TokenType::Semicolon = self.previous().token_type {
+            if matches!(
+                self.previous().token_type,
+                TokenType::Semicolon
+                    | TokenType::Endifelseblock
+                    | TokenType::EndWhile
+                    | TokenType::EndFor
+                    | TokenType::EndFunc
+            ) {
                 return;
             }
-
-            match self.peek().token_type {
-                TokenType::Var | TokenType::Display => return,
-                _ => {
-                    self.advance();
-                }
+            if self.at_statement_boundary() {
+                return;
             }
+            self.advance();
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::token::Lexer;
+
+    /// Statements of a program that must parse without diagnostics.
+    fn parse(program: &str) -> Vec<ASTNode> {
+        let mut lexer = Lexer::new();
+        lexer.tokenize(program);
+        let (ast, diagnostics) = Parser::new(lexer.return_tok()).parse();
+        assert!(diagnostics.is_empty(), "{}: {:?}", program, diagnostics);
+        match ast {
+            ASTNode::Program(statements) => statements,
+            other => panic!("{}: not a program: {:?}", program, other),
+        }
+    }
+
+    /// Parses `source` as the initializer of a declaration and prints it fully parenthesized.
+    fn shape(source: &str) -> String {
+        match &parse(&format!("VARint x = {};\nEND\n", source))[0] {
+            ASTNode::VariableDeclaration {
+                initializer: Some(initializer),
+                ..
+            } => render(initializer),
+            other => panic!("{}: not a declaration: {:?}", source, other),
+        }
+    }
+
+    fn render(node: &ASTNode) -> String {
+        match node {
+            ASTNode::BinaryOp {
+                operator,
+                left,
+                right,
+                ..
+            } => format!("({} {} {})", render(left), operator, render(right)),
+            ASTNode::UnaryOp {
+                operator, operand, ..
+            } => format!("({} {})", operator, render(operand)),
+            ASTNode::Number(value, _)
+            | ASTNode::Float(value, _)
+            | ASTNode::Identifier(value, _) => value.clone(),
+            ASTNode::Boolean(value, _) => value.to_string(),
+            other => panic!("unexpected node {:?}", other),
+        }
+    }
+
+    #[test]
+    fn binary_operator_shapes() {
+        let cases = [
+            ("1 + 2 * 3", "(1 + (2 * 3))"),
+            ("1 * 2 + 3", "((1 * 2) + 3)"),
+            ("1 - 2 - 3", "((1 - 2) - 3)"),
+            ("8 / 4 / 2", "((8 / 4) / 2)"),
+            ("1 - 2 + 3", "((1 - 2) + 3)"),
+            ("(1 + 2) * 3", "((1 + 2) * 3)"),
+            ("a < b + 1", "(a < (b + 1))"),
+            ("a * 2 >= b - 1", "((a * 2) >= (b - 1))"),
+            ("a < b == c > d", "((a < b) == (c > d))"),
+            ("a == b != c", "((a == b) != c)"),
+            ("a + b * c - d / e", "((a + (b * c)) - (d / e))"),
+            ("NOT a < b", "(NOT (a < b))"),
+            ("-a * b", "((- a) * b)"),
+            ("a - -b", "(a - (- b))"),
+            ("- -a + 1", "((- (- a)) + 1)"),
+            ("-(a + b)", "(- (a + b))"),
+            ("-a < b", "((- a) < b)"),
+            ("a + b % c", "(a + (b % c))"),
+            ("a % b * c", "((a % b) * c)"),
+            ("a * b ** c", "(a * (b ** c))"),
+            ("a ** b ** c", "(a ** (b ** c))"),
+            ("-a ** b", "(- (a ** b))"),
+            ("a ** -b", "(a ** (- b))"),
+            ("1.5 * 2e-3 - 1e+2", "((1.5 * 2e-3) - 1e+2)"),
+            ("2e-3-1", "(2e-3 - 1)"),
+            ("a < b AND c OR d", "(((a < b) AND c) OR d)"),
+            ("a OR b AND NOT c", "(a OR (b AND (NOT c)))"),
+        ];
+        for (source, expected) in cases {
+            assert_eq!(shape(source), expected, "{}", source);
+        }
+    }
+
+    #[test]
+    fn else_if_branches() {
+        let program = parse(
+            "VARint n = 2;\n\
+             IF (n == 1) THEN\n  DISPLAYstring \"one\";\n\
+             ELSE-IF (n == 2 OR n < 0) THEN\n  DISPLAYstring \"two\";\n  DISPLAYnumeric n;\n\
+             ELIF (n) THEN\n  DISPLAYstring \"other\";\n\
+             ELSE\n  DISPLAYstring \"zero\";\n\
+             END-IF\nEND\n",
+        );
+        let ASTNode::IfStatement {
+            condition,
+            then_block,
+            else_if_blocks,
+            else_block,
+            ..
+        } = &program[1]
+        else {
+            panic!("not an IF: {:?}", program[1]);
+        };
+        assert_eq!(render(condition), "(n == 1)");
+        assert_eq!(then_block.len(), 1);
+        let branches: Vec<_> = else_if_blocks
+            .iter()
+            .map(|(condition, block)| (render(condition), block.len()))
+            .collect();
+        assert_eq!(
+            branches,
+            [("((n == 2) OR (n < 0))".to_string(), 2), ("n".to_string(), 1)]
+        );
+        assert_eq!(else_block.as_ref().map(Vec::len), Some(1));
+    }
+
+    /// Statements that survived a parse with syntax errors, and its diagnostics.
+    fn parse_with_errors(program: &str) -> (Vec<ASTNode>, Vec<Diagnostic>) {
+        let mut lexer = Lexer::new();
+        lexer.tokenize(program);
+        match Parser::new(lexer.return_tok()).parse() {
+            (ASTNode::Program(statements), diagnostics) => (statements, diagnostics),
+            (other, _) => panic!("{}: not a program: {:?}", program, other),
+        }
+    }
+
+    #[test]
+    fn one_parse_reports_every_error_and_keeps_the_rest() {
+        let program = "VARint a = ;\nVARint b = 2;\n\
+                       IF (b > 1) THEN\n    VARint c = * 2;\n    DISPLAY b;\nEND-IF\n\
+                       WHILE (b < 3) DO\n    b = b +;\nEND-WHILE\n\
+                       FOR i = 1 TO\n    DISPLAY i;\nEND-FOR\n\
+                       FUNC f(VARint x) RETURNS int\n    RETURN x +;\nEND-FUNC\n\
+                       DISPLAY b;\nEND\n";
+        let (statements, diagnostics) = parse_with_errors(program);
+        let errors: Vec<_> = diagnostics.iter().map(|d| (d.code, d.span.line)).collect();
+        assert_eq!(
+            errors,
+            [
+                ("E0101", 1),
+                ("E0101", 4),
+                ("E0101", 8),
+                ("E0101", 11),
+                ("E0101", 14)
+            ]
+        );
+        //Bad statements are dropped; the blocks around them keep what did parse
+        assert!(
+            matches!(
+                &statements[..],
+                [
+                    ASTNode::VariableDeclaration { .. },
+                    ASTNode::IfStatement { then_block, .. },
+                    ASTNode::WhileStatement { body, .. },
+                    ASTNode::FunctionDeclaration { .. },
+                    ASTNode::DisplayStatement(..),
+                ] if then_block.len() == 1 && body.is_empty()
+            ),
+            "{:?}",
+            statements
+        );
+    }
+
+    #[test]
+    fn missing_end_on_empty_input_points_at_line_one() {
+        let (statements, diagnostics) = parse_with_errors("");
+        assert!(statements.is_empty());
+        let errors: Vec<_> = diagnostics
+            .iter()
+            .map(|d| (d.code, d.span.line, d.span.column))
+            .collect();
+        assert_eq!(errors, [("E0104", 1, 1)]);
+    }
+}
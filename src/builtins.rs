@@ -0,0 +1,40 @@
+use crate::token::Type;
+
+/// A function every program can call without declaring it. Calls parse like any other
+/// call; each stage looks the name up here before the program's own functions, which
+/// may not reuse these names.
+#[derive(Debug)]
+pub struct Builtin {
+    pub name: &'static str,
+    pub params: &'static [Type],
+    pub returns: Type,
+}
+
+/// Strings are measured and indexed in characters, with MID's start counted from 1.
+/// VAL reads an optionally signed integer, allowing spaces and tabs around it.
+const BUILTINS: &[Builtin] = &[
+    Builtin {
+        name: "LEN",
+        params: &[Type::Str],
+        returns: Type::Int,
+    },
+    Builtin {
+        name: "MID",
+        params: &[Type::Str, Type::Int, Type::Int],
+        returns: Type::Str,
+    },
+    Builtin {
+        name: "STR",
+        params: &[Type::Int],
+        returns: Type::Str,
+    },
+    Builtin {
+        name: "VAL",
+        params: &[Type::Str],
+        returns: Type::Int,
+    },
+];
+
+pub fn lookup(name: &str) -> Option<&'static Builtin> {
+    BUILTINS.iter().find(|builtin| builtin.name == name)
+}
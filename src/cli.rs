@@ -0,0 +1,201 @@
+use std::path::PathBuf;
+
+pub const USAGE: &str = "\
+Usage: LanguageDevelopment <input.sco> [options]
+       LanguageDevelopment build <input.sco> [options]
+       LanguageDevelopment run <input.sco> [options]
+       LanguageDevelopment interp <input.sco>
+       LanguageDevelopment repl
+
+Commands:
+    (none)                    Translate the program to C
+    build                     Translate the program and compile it to an executable
+    run                       Compile the program into a temp directory and execute it
+    interp                    Execute the program with the built-in interpreter (no C compiler needed)
+    repl                      Start an interactive session
+
+Options:
+    -o <file>                 Write output to <file> ('-' for stdout)
+    --emit <tokens|ast|c>     Stop after the given stage (default: c)
+    --cc <compiler>           C compiler used by build (default: $CC, cc, gcc, clang)
+    --cflags <flags>          Extra flags passed to the C compiler
+    --keep-temps              Keep the generated C and temporary executable
+    -h, --help                Print this message";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Command {
+    Compile,
+    Build,
+    Run,
+    Interp,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Emit {
+    Tokens,
+    Ast,
+    C,
+}
+
+impl Emit {
+    fn from_arg(arg: &str) -> Result<Self, String> {
+        match arg {
+            "tokens" => Ok(Emit::Tokens),
+            "ast" => Ok(Emit::Ast),
+            "c" => Ok(Emit::C),
+            _ => Err(format!(
+                "Unknown stage '{}', expected one of tokens, ast, c",
+                arg
+            )),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Output {
+    Stdout,
+    File(PathBuf),
+}
+
+#[derive(Debug)]
+pub struct Options {
+    pub command: Command,
+    pub input: PathBuf,
+    pub output: Option<Output>,
+    pub emit: Emit,
+    pub cc: Option<String>,
+    pub cflags: Vec<String>,
+    pub keep_temps: bool,
+}
+
+impl Options {
+    /// Where the selected stage gets written. Tokens and AST dumps go to stdout
+    /// unless `-o` is given; C source lands next to the input as `<name>.c`.
+    pub fn output(&self) -> Output {
+        match (&self.output, self.emit) {
+            (Some(out), _) => out.clone(),
+            (None, Emit::Tokens) | (None, Emit::Ast) => Output::Stdout,
+            (None, Emit::C) => Output::File(self.input.with_extension("c")),
+        }
+    }
+
+    /// The executable produced by `build`, next to the source unless `-o` says otherwise.
+    pub fn executable(&self) -> PathBuf {
+        if let Some(Output::File(path)) = &self.output {
+            return path.clone();
+        }
+        let mut exe = self.input.with_extension(std::env::consts::EXE_EXTENSION);
+        if exe == self.input {
+            exe = self.input.with_extension("out");
+        }
+        exe
+    }
+}
+
+pub enum Parsed {
+    Run(Options),
+    Repl,
+    Help,
+}
+
+pub fn parse_args<I>(args: I) -> Result<Parsed, String>
+where
+    I: IntoIterator<Item = String>,
+{
+    let mut args = args.into_iter().peekable();
+    let mut input: Option<PathBuf> = None;
+    let mut output = None;
+    let mut emit = None;
+    let mut cc = None;
+    let mut cflags = Vec::new();
+    let mut keep_temps = false;
+
+    let command = match args.peek().map(String::as_str) {
+        Some("build") => {
+            args.next();
+            Command::Build
+        }
+        Some("run") => {
+            args.next();
+            Command::Run
+        }
+        Some("interp") => {
+            args.next();
+            Command::Interp
+        }
+        Some("repl") => {
+            args.next();
+            return match args.next() {
+                None => Ok(Parsed::Repl),
+                Some(arg) if arg == "-h" || arg == "--help" => Ok(Parsed::Help),
+                Some(arg) => Err(format!("'repl' does not take arguments, got '{}'", arg)),
+            };
+        }
+        _ => Command::Compile,
+    };
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-h" | "--help" => return Ok(Parsed::Help),
+            "-o" => {
+                let value = args.next().ok_or("Missing file name after '-o'")?;
+                output = Some(if value == "-" {
+                    Output::Stdout
+                } else {
+                    Output::File(PathBuf::from(value))
+                });
+            }
+            "--emit" => {
+                let value = args.next().ok_or("Missing stage after '--emit'")?;
+                emit = Some(Emit::from_arg(&value)?);
+            }
+            _ if arg.starts_with("--emit=") => {
+                emit = Some(Emit::from_arg(&arg["--emit=".len()..])?);
+            }
+            "--cc" => {
+                cc = Some(args.next().ok_or("Missing compiler after '--cc'")?);
+            }
+            "--cflags" => {
+                let value = args.next().ok_or("Missing flags after '--cflags'")?;
+                cflags.extend(value.split_whitespace().map(String::from));
+            }
+            "--keep-temps" => keep_temps = true,
+            _ if arg.starts_with('-') && arg != "-" => {
+                return Err(format!("Unknown option '{}'", arg));
+            }
+            _ => {
+                if input.is_some() {
+                    return Err(format!("Unexpected extra input file '{}'", arg));
+                }
+                input = Some(PathBuf::from(arg));
+            }
+        }
+    }
+
+    let input = input.ok_or("No input file given")?;
+    match command {
+        Command::Compile => {}
+        Command::Build => {
+            if emit.is_some() {
+                return Err("'--emit' cannot be combined with 'build'".to_string());
+            }
+            if output == Some(Output::Stdout) {
+                return Err("'build' cannot write an executable to stdout".to_string());
+            }
+        }
+        Command::Run | Command::Interp => {
+            if emit.is_some() || output.is_some() {
+                return Err("'run' and 'interp' do not take '--emit' or '-o'".to_string());
+            }
+        }
+    }
+    Ok(Parsed::Run(Options {
+        command,
+        input,
+        output,
+        emit: emit.unwrap_or(Emit::C),
+        cc,
+        cflags,
+        keep_temps,
+    }))
+}
@@ -1,274 +1,1303 @@
 use crate::ast;
-
-use crate::token::Type;
+use crate::builtins;
+use crate::diagnostic::Diagnostic;
+use crate::scope::ScopeStack;
+use crate::sema;
+use crate::token::{Span, Type};
 use std::collections::HashMap;
-use std::env::var;
 use std::fs::File;
-use std::hash::Hash;
 use std::io::Write;
+use std::path::Path;
+/// `**` on long longs. Multiplication goes through unsigned so overflow wraps like the
+/// interpreter; a negative exponent truncates 1 / base ** -exp, so a zero base divides
+/// by zero.
+const IPOW_HELPER: &str = "\
+static long long sco_ipow(long long base, long long exp, const char *where) {
+    if (exp < 0 && base == 0) {
+        fprintf(stderr, \"error[E0402]: Division by zero\\n --> %s\\n\", where);
+        exit(1);
+    }
+    if (exp < 0) return base == -1 ? (exp % 2 ? -1 : 1) : 1 / base;
+    unsigned long long result = 1, b = (unsigned long long)base;
+    for (; exp > 0; exp >>= 1) {
+        if (exp & 1) result *= b;
+        b *= b;
+    }
+    return (long long)result;
+}
+
+";
+
+/// Int `/`, reporting division by zero like the interpreter instead of trapping.
+/// LLONG_MIN / -1 wraps back to LLONG_MIN, as `wrapping_div` does.
+const IDIV_HELPER: &str = "\
+static long long sco_idiv(long long a, long long b, const char *where) {
+    if (b == 0) {
+        fprintf(stderr, \"error[E0402]: Division by zero\\n --> %s\\n\", where);
+        exit(1);
+    }
+    if (b == -1) return (long long)(0ULL - (unsigned long long)a);
+    return a / b;
+}
+
+";
+
+/// Int `%`, checked like `sco_idiv`; anything % -1 is 0, LLONG_MIN included.
+const IMOD_HELPER: &str = "\
+static long long sco_imod(long long a, long long b, const char *where) {
+    if (b == 0) {
+        fprintf(stderr, \"error[E0402]: Division by zero\\n --> %s\\n\", where);
+        exit(1);
+    }
+    if (b == -1) return 0;
+    return a % b;
+}
+
+";
+
+/// Strings are values: each Str expression yields a fresh heap copy that whoever
+/// consumes it (a variable, an argument, a return) owns and eventually frees. The length
+/// is kept so text with `\u{0}` in it survives.
+const STRING_RUNTIME: &str = "\
+#include <string.h>
+
+typedef struct {
+    char *data;
+    size_t len;
+} sco_str;
+
+static char *sco_alloc(size_t size) {
+    char *data = malloc(size);
+    if (!data) {
+        fputs(\"error: out of memory\\n\", stderr);
+        exit(1);
+    }
+    return data;
+}
+
+static sco_str sco_str_from(const char *text, size_t len) {
+    sco_str s = { sco_alloc(len + 1), len };
+    memcpy(s.data, text, len);
+    s.data[len] = '\\0';
+    return s;
+}
+
+static sco_str sco_str_copy(sco_str s) {
+    return sco_str_from(s.data, s.len);
+}
+
+static void sco_str_free(sco_str s) {
+    free(s.data);
+}
+
+/* Gives *target the value, which it takes ownership of, and frees the old one */
+static void sco_str_set(sco_str *target, sco_str value) {
+    free(target->data);
+    *target = value;
+}
+
+static void sco_str_print(sco_str s) {
+    fwrite(s.data, 1, s.len, stdout);
+    putchar('\\n');
+}
+
+";
+
+/// String operators and built-ins. Each one consumes the strings it is given. Lengths
+/// and MID positions count characters, so UTF-8 continuation bytes are skipped.
+const STRING_OPS: &str = "\
+static sco_str sco_str_concat(sco_str a, sco_str b) {
+    sco_str s = { sco_alloc(a.len + b.len + 1), a.len + b.len };
+    memcpy(s.data, a.data, a.len);
+    memcpy(s.data + a.len, b.data, b.len);
+    s.data[s.len] = '\\0';
+    sco_str_free(a);
+    sco_str_free(b);
+    return s;
+}
+
+/* Byte order, like memcmp, with a prefix ordered first */
+static int sco_str_compare(sco_str a, sco_str b) {
+    size_t len = a.len < b.len ? a.len : b.len;
+    int result = memcmp(a.data, b.data, len);
+    if (result == 0) result = (a.len > b.len) - (a.len < b.len);
+    sco_str_free(a);
+    sco_str_free(b);
+    return result;
+}
+
+static int sco_is_char_start(char c) {
+    return ((unsigned char)c & 0xC0) != 0x80;
+}
+
+static long long sco_str_len(sco_str s) {
+    long long count = 0;
+    for (size_t i = 0; i < s.len; i++) count += sco_is_char_start(s.data[i]);
+    sco_str_free(s);
+    return count;
+}
+
+/* Byte offset of the character `index` characters after byte `from` */
+static size_t sco_str_skip(sco_str s, size_t from, long long index) {
+    for (; from < s.len; from++) {
+        if (sco_is_char_start(s.data[from]) && index-- == 0) break;
+    }
+    return from;
+}
+
+static sco_str sco_str_mid(sco_str s, long long start, long long count, const char *where) {
+    if (start < 1 || count < 0) {
+        fprintf(stderr, \"error[E0409]: MID needs a start of at least 1 and a count of at least 0\\n --> %s\\n\", where);
+        exit(1);
+    }
+    size_t from = sco_str_skip(s, 0, start - 1);
+    size_t to = sco_str_skip(s, from, count);
+    sco_str result = sco_str_from(s.data + from, to - from);
+    sco_str_free(s);
+    return result;
+}
+
+";
+
+/// `STR`. Each conversion helper is required on its own, so a program only carries the
+/// ones it calls.
+const STR_HELPER: &str = "\
+static sco_str sco_str_of_int(long long n) {
+    char text[32];
+    int len = snprintf(text, sizeof text, \"%lld\", n);
+    return sco_str_from(text, (size_t)len);
+}
+
+";
+
+/// Int parsing shared by `VAL` and ACCEPTnumeric.
+const PARSE_INT_HELPER: &str = "\
+/* Reads an optionally signed run of digits within long long range, with nothing around
+   it but spaces and tabs, into *out; false if s holds anything else */
+static bool sco_parse_int(sco_str s, long long *out) {
+    size_t i = 0, end = s.len;
+    while (i < end && (s.data[i] == ' ' || s.data[i] == '\\t')) i++;
+    while (end > i && (s.data[end - 1] == ' ' || s.data[end - 1] == '\\t')) end--;
+    bool negative = i < end && s.data[i] == '-';
+    if (i < end && (s.data[i] == '-' || s.data[i] == '+')) i++;
+    unsigned long long value = 0;
+    unsigned long long limit = negative ? 9223372036854775808ULL : 9223372036854775807ULL;
+    bool valid = i < end;
+    for (; valid && i < end; i++) {
+        unsigned digit = (unsigned char)s.data[i] - '0';
+        valid = digit <= 9 && value <= (limit - digit) / 10;
+        value = value * 10 + digit;
+    }
+    if (valid) *out = negative ? (long long)(0 - value) : (long long)value;
+    return valid;
+}
+
+";
+
+/// `VAL`, which needs `PARSE_INT_HELPER` ahead of it.
+const VAL_HELPER: &str = "\
+static long long sco_str_val(sco_str s, const char *where) {
+    long long value;
+    bool valid = sco_parse_int(s, &value);
+    sco_str_free(s);
+    if (!valid) {
+        fprintf(stderr, \"error[E0409]: VAL needs the text of an integer in Int range\\n --> %s\\n\", where);
+        exit(1);
+    }
+    return value;
+}
+
+";
+
+/// ACCEPTnumeric into a Float. `strtod` alone would also take hex, `nan(...)` and
+/// leading newlines, so the text is checked first to match what the interpreter reads.
+const PARSE_FLOAT_HELPER: &str = "\
+/* Reads a decimal number, inf or nan, with nothing around it but spaces and tabs, into
+   *out; false if s holds anything else */
+static bool sco_parse_float(sco_str s, double *out) {
+    size_t i = 0, end = s.len;
+    while (i < end && (s.data[i] == ' ' || s.data[i] == '\\t')) i++;
+    while (end > i && (s.data[end - 1] == ' ' || s.data[end - 1] == '\\t')) end--;
+    for (size_t j = i; j < end; j++) {
+        if (s.data[j] == '\\0' || !strchr(\"0123456789+-.eEinfatyINFATY\", s.data[j])) return false;
+    }
+    char *stop;
+    double value = strtod(s.data + i, &stop);
+    if (i == end || stop != s.data + end) return false;
+    *out = value;
+    return true;
+}
+
+";
+
+/// ACCEPT input. Lines are read in `fgets` sized pieces, so any length works.
+const INPUT_RUNTIME: &str = "\
+/* Reads the next line of standard input into *line without its line ending; false,
+   leaving nothing to free, once the input is used up */
+static bool sco_read_line(sco_str *line) {
+    char chunk[256];
+    bool read = false;
+    fflush(stdout);
+    *line = sco_str_from(\"\", 0);
+    while (fgets(chunk, sizeof chunk, stdin)) {
+        size_t len = strlen(chunk);
+        char *data = sco_alloc(line->len + len + 1);
+        memcpy(data, line->data, line->len);
+        memcpy(data + line->len, chunk, len + 1);
+        free(line->data);
+        line->data = data;
+        line->len += len;
+        read = true;
+        if (line->len > 0 && line->data[line->len - 1] == '\\n') break;
+    }
+    if (!read) {
+        sco_str_free(*line);
+        return false;
+    }
+    if (line->len > 0 && line->data[line->len - 1] == '\\n') {
+        line->len--;
+        if (line->len > 0 && line->data[line->len - 1] == '\\r') line->len--;
+        line->data[line->len] = '\\0';
+    }
+    return true;
+}
+
+";
+
 pub struct CodeGenerator {
-    variables: HashMap<String, VariableInfo>,
+    variables: ScopeStack<VariableInfo>,
     c_code: String,
+    source_name: String,
+    diagnostics: Vec<Diagnostic>,
+    temp_counter: usize,
+    //Runtime helpers used so far, in the order they go into the C file
+    runtime: Vec<&'static str>,
+    function_types: HashMap<String, Option<Type>>,
+    //Return type of the function being generated
+    returns: Option<Type>,
     //tree_properties:
 }
 
 struct VariableInfo {
-    linenum: u32,
+    //Name in the generated C; differs from the source name when it shadows another
+    c_name: String,
+    var_type: Type,
 }
 
 impl CodeGenerator {
     pub fn new() -> Self {
         CodeGenerator {
-            variables: HashMap::new(),
+            variables: ScopeStack::new(),
             c_code: String::new(),
+            source_name: "<input>".to_string(),
+            diagnostics: Vec::new(),
+            temp_counter: 0,
+            runtime: Vec::new(),
+            function_types: HashMap::new(),
+            returns: None,
         }
     }
 
-    pub fn generate(&self, mut ast: ast::ASTNode) {
-        Self::process_ast(ast)
-    }
+    pub fn generate_ir(&mut self, ast: ast::ASTNode) -> String {
+        self.c_code.clear();
+        self.diagnostics.clear();
+        self.temp_counter = 0;
+        self.runtime.clear();
+        self.function_types.clear();
+        self.variables = ScopeStack::new();
+        self.c_code.push_str("#include <stdbool.h>\n");
+        self.c_code.push_str("#include <stdio.h>\n");
+        self.c_code.push_str("#include <stdlib.h>\n\n");
+        let helpers_at = self.c_code.len();
 
-    fn process_ast(node: ast::ASTNode) {
-        //flashing lights, big city
-        match node {
-            ast::ASTNode::Program(statements) => {
-                println!("Program with {} statements", statements.len());
-                for statement in statements {
-                    Self::process_ast(statement);
-                }
-            }
-            ast::ASTNode::VariableDeclaration {
-                identifier,
-                initializer,
-                line,
-                var_type,
-            } => {
-                println!(
-                    "Variable Declaration: {} on line {} of type {}",
-                    identifier,
-                    line,
-                    var_type
-                        .as_ref()
-                        .map_or("Unknown".to_string(), |t| t.to_string())
-                );
-                if let Some(init) = initializer {
-                    println!("  Initialized with:");
-                    Self::process_ast(*init);
+        //Functions become real C functions ahead of main; prototypes first, so calls
+        //work in any order and recursion needs nothing special
+        let statements = match ast {
+            ast::ASTNode::Program(statements) => statements,
+            other => vec![other],
+        };
+        let (functions, main_body): (Vec<_>, Vec<_>) = statements
+            .into_iter()
+            .partition(|s| matches!(s, ast::ASTNode::FunctionDeclaration { .. }));
+        for function in &functions {
+            if let ast::ASTNode::FunctionDeclaration {
+                name,
+                params,
+                return_type,
+                ..
+            } = function
+            {
+                if return_type == &Some(Type::Str) || params.iter().any(|p| p.var_type == Type::Str)
+                {
+                    self.require(STRING_RUNTIME);
                 }
+                self.function_types
+                    .insert(name.clone(), return_type.clone());
             }
-            ast::ASTNode::DisplayStatement(identifier, line) => {
-                println!("Display Statement: {} on line {}", identifier, line);
-            }
-            ast::ASTNode::DisplayIntStatement(identifier, line) => {
-                println!("Display int Statement: {} on line {}", identifier, line);
-            }
-            ast::ASTNode::DisplayStringStatement(identifier, line) => {
-                println!("Display string Statement: {} on line {}", identifier, line);
-            }
-            ast::ASTNode::DisplayStringVariable(identifier, line) => {
-                println!("Display string Statement: {} on line {}", identifier, line);
-            }
-            ast::ASTNode::ExpressionStatement {
-                expression,
-                identifier,
-                line,
-            } => {
-                println!(
-                    "Expression Statement: {} = ... on line {}",
-                    identifier, line
-                );
-                Self::process_ast(*expression);
-            }
+            let prototype = format!("{};\n", Self::function_signature(function));
+            self.c_code.push_str(&prototype);
+        }
+        if !functions.is_empty() {
+            self.c_code.push('\n');
+        }
+        for function in functions {
+            self.gen_statement(function);
+        }
+
+        self.c_code.push_str("int main() {\n");
+        for statement in main_body {
+            self.gen_statement(statement);
+        }
+        let frees = self.free_strings(self.variables.all());
+        self.c_code.push_str(&frees);
+        self.c_code.push_str("    return 0;\n");
+        self.c_code.push_str("}\n");
+        //Runtime helpers are only added once something has used them
+        self.c_code.insert_str(helpers_at, &self.runtime.concat());
+        self.c_code.clone()
+    }
+
+    /// Name used in `#line` directives so C compiler errors point at the .sco file.
+    pub fn set_source_name(&mut self, name: &str) {
+        self.source_name = name.to_string();
+    }
+
+    fn gen_statement(&mut self, node: ast::ASTNode) {
+        if let Some(span) = node.span() {
+            self.line_directive(span);
+        }
+        self.gen_ir_ast(node);
+    }
+
+    fn line_directive(&mut self, span: Span) {
+        self.c_code.push_str(&format!(
+            "#line {} \"{}\"\n",
+            span.line,
+            self.escaped_source_name()
+        ));
+    }
+
+    fn escaped_source_name(&self) -> String {
+        escape_c(&self.source_name)
+    }
+
+    /// C statement that reports a runtime error the way the interpreter renders its
+    /// header and location, then exits with status 1.
+    fn runtime_error(&self, code: &str, message: &str, span: Span) -> String {
+        format!(
+            "{{ fprintf(stderr, \"error[{}]: {}\\n --> {}:{}:{}\\n\"); exit(1); }}",
+            code,
+            message,
+            self.escaped_source_name().replace('%', "%%"),
+            span.line,
+            span.column
+        )
+    }
+
+    /// `"file:line:col"` as a C string, for runtime helpers that report their own errors.
+    fn location(&self, span: Span) -> String {
+        format!(
+            "\"{}:{}:{}\"",
+            self.escaped_source_name(),
+            span.line,
+            span.column
+        )
+    }
+
+    fn require(&mut self, helper: &'static str) {
+        if !self.runtime.contains(&helper) {
+            self.runtime.push(helper);
+        }
+    }
+
+    /// String operators and built-ins, placed after the string runtime they build on.
+    fn require_string_ops(&mut self) {
+        self.require(STRING_RUNTIME);
+        self.require(STRING_OPS);
+    }
+
+    fn require_input(&mut self) {
+        self.require(STRING_RUNTIME);
+        self.require(INPUT_RUNTIME);
+    }
+
+    /// `sco_str_free` calls for the Str variables among `variables`, in a stable order.
+    fn free_strings<'v>(
+        &self,
+        variables: impl Iterator<Item = (&'v String, &'v VariableInfo)>,
+    ) -> String {
+        let mut names: Vec<&str> = variables
+            .filter(|(_, info)| info.var_type == Type::Str)
+            .map(|(_, info)| info.c_name.as_str())
+            .collect();
+        names.sort_unstable();
+        names
+            .iter()
+            .map(|name| format!("sco_str_free({});\n", name))
+            .collect()
+    }
+
+    /// C expression for a variable that was declared without an initializer; matches
+    /// the interpreter's defaults.
+    fn default_value(var_type: &Type) -> &'static str {
+        match var_type {
+            Type::Str => "sco_str_from(\"\", 0)",
+            Type::Bool => "false",
+            Type::Float => "0.0",
+            _ => "0LL",
+        }
+    }
+
+    fn c_operator(operator: &str) -> &str {
+        match operator {
+            "AND" => "&&",
+            "OR" => "||",
+            "NOT" => "!",
+            other => other,
+        }
+    }
+
+    /// C type as written in front of a name, e.g. `long long ` or `sco_str `.
+    fn c_type(var_type: Option<&Type>) -> &'static str {
+        match var_type {
+            None => "void ",
+            Some(Type::Str) => "sco_str ",
+            Some(Type::Bool) => "bool ",
+            Some(Type::Float) => "double ",
+            Some(_) => "long long ",
+        }
+    }
+
+    /// `long long sco_fn_name(long long a, sco_str b)`; user functions get a prefix so they
+    /// can't clash with the C library, `main`, or the `sco_` runtime helpers and temps.
+    fn function_signature(function: &ast::ASTNode) -> String {
+        let ast::ASTNode::FunctionDeclaration {
+            name,
+            params,
+            return_type,
+            ..
+        } = function
+        else {
+            unreachable!("not a function declaration")
+        };
+        let params = if params.is_empty() {
+            "void".to_string()
+        } else {
+            params
+                .iter()
+                .map(|p| {
+                    format!(
+                        "{}{}",
+                        Self::c_type(Some(&p.var_type)),
+                        Self::variable_name(&p.name)
+                    )
+                })
+                .collect::<Vec<_>>()
+                .join(", ")
+        };
+        format!(
+            "{}sco_fn_{}({})",
+            Self::c_type(return_type.as_ref()),
+            name,
+            params
+        )
+    }
+
+    /// C name that `name` currently refers to.
+    fn c_name(&self, name: &str) -> String {
+        self.variables
+            .get(name)
+            .map_or_else(|| name.to_string(), |info| info.c_name.clone())
+    }
+
+    /// C name of a user variable or parameter. The prefix keeps names like `true`, `bool`,
+    /// `free` or `int` clear of C keywords, the C library and the runtime.
+    fn variable_name(name: &str) -> String {
+        format!("sco_v_{}", name)
+    }
+
+    /// C name for a new declaration of `name`. In C a declaration is already in scope
+    /// in its own initializer, so one that shadows a visible variable gets a numbered
+    /// name, `sco_v2_x`, and `VARint x = x + 1;` still reads the outer `x`.
+    fn declaration_name(&mut self, name: &str) -> String {
+        if self.variables.get(name).is_some() {
+            self.temp_counter += 1;
+            format!("sco_v{}_{}", self.temp_counter, name)
+        } else {
+            Self::variable_name(name)
+        }
+    }
+
+    fn gen_block(&mut self, statements: Vec<ast::ASTNode>) {
+        self.variables.push();
+        for stmt in statements {
+            self.gen_statement(stmt);
+        }
+        let frees = self.free_strings(self.variables.locals());
+        self.c_code.push_str(&frees);
+        self.variables.pop();
+    }
+
+    fn is_string(&self, name: &str) -> bool {
+        self.variables
+            .get(name)
+            .is_some_and(|info| info.var_type == Type::Str)
+    }
+
+    /// Type of the value an expression computes, by the checker's operator rules;
+    /// `Unknown` if it has none.
+    fn expr_type(&self, node: &ast::ASTNode) -> Type {
+        match node {
+            ast::ASTNode::Number(..) => Type::Int,
+            ast::ASTNode::Float(..) => Type::Float,
+            ast::ASTNode::StringLiteral(..) => Type::Str,
+            ast::ASTNode::Boolean(..) => Type::Bool,
+            ast::ASTNode::Identifier(name, _) => self
+                .variables
+                .get(name)
+                .map_or(Type::Unknown, |info| info.var_type.clone()),
+            ast::ASTNode::Call { name, .. } => match builtins::lookup(name) {
+                Some(builtin) => builtin.returns.clone(),
+                None => self
+                    .function_types
+                    .get(name)
+                    .cloned()
+                    .flatten()
+                    .unwrap_or(Type::Unknown),
+            },
+            ast::ASTNode::UnaryOp {
+                operator, operand, ..
+            } => sema::unary_type(operator, &self.expr_type(operand)).1,
             ast::ASTNode::BinaryOp {
                 operator,
                 left,
                 right,
-                line,
-            } => {
-                println!("Binary Operation: {} on line {}", operator, line);
-                println!("  Left:");
-                Self::process_ast(*left);
-                println!("  Right:");
-                Self::process_ast(*right);
-            }
-            ast::ASTNode::Identifier(name, line) => {
-                println!("Identifier: {} on line {}", name, line);
-            }
-            ast::ASTNode::Number(value, line) => {
-                println!("Number: {} on line {}", value, line);
-            }
-            ast::ASTNode::StringLiteral(value, line) => {
-                println!("String Literal: \"{}\" on line {}", value, line);
-            }
-
-            ast::ASTNode::IfStatement {
-                condition,
-                then_block,
-                else_if_blocks,
-                else_block,
-                line,
-            } => {
-                match else_block {
-                    None => {
-                        println!(
-                            "If Statement:{:?} Then block{:?},elseif block{:?}  line{:?}",
-                            condition, then_block, else_if_blocks, line
-                        );
-                    }
-
-                    Some(_) => {
-                        println!("If Statement:{:?} Then block{:?},elseif block{:?} else block{:?} line{:?}",condition,then_block,else_if_blocks,else_block,line);
-                    }
-                }
-            }
+                ..
+            } => sema::binary_type(operator, &self.expr_type(left), &self.expr_type(right)).1,
+            _ => Type::Unknown,
         }
     }
 
-    pub fn generate_ir(&mut self, ast: ast::ASTNode) -> String {
-        println!("generating C code");
-        self.c_code.clear();
-        self.c_code.push_str("#include <stdio.h>\n\n");
-        self.c_code.push_str("int main() {\n");
-        self.gen_ir_ast(ast);
-        self.c_code.push_str("    return 0;\n");
-        self.c_code.push_str("}\n");
-        self.c_code.clone()
+    /// Fresh name for a compiler-generated C variable.
+    fn temp(&mut self, prefix: &str) -> String {
+        self.temp_counter += 1;
+        format!("sco_{}_{}", prefix, self.temp_counter)
     }
 
     fn gen_ir_ast(&mut self, node: ast::ASTNode) {
         match node {
             ast::ASTNode::Program(statements) => {
                 for statement in statements {
-                    self.gen_ir_ast(statement);
+                    self.gen_statement(statement);
                 }
             }
             ast::ASTNode::VariableDeclaration {
                 identifier,
                 initializer,
-                line,
+                span,
                 var_type,
             } => {
-                let mut fact = true;
-                let type_str = var_type
-                    .as_ref()
-                    .map_or("Unknown".to_string(), |t| t.to_string());
-                match type_str.as_str() {
-                    "Int" => {
-                        self.c_code.push_str(&format!("int {};\n", identifier));
-                        fact = false;
-                    }
-                    "Str" => {
-                        self.c_code.push_str(&format!("char {}[]=", identifier));
-                        // Assuming a max length of 100
-                    }
-                    _ => {
-                        self.c_code
-                            .push_str(&format!("/* Unknown type */ void* {};\n", identifier));
-                    }
+                let c_name = self.declaration_name(&identifier);
+                let var_type = var_type.unwrap_or(Type::Unknown);
+                if var_type == Type::Unknown {
+                    self.diagnostics.push(Diagnostic::error(
+                        "E0300",
+                        format!("Variable '{}' has no known type", identifier),
+                        span,
+                    ));
+                    self.c_code
+                        .push_str(&format!("/* Unknown type */ void* {};\n", c_name));
+                    return;
                 }
-                self.variables
-                    .insert(identifier.clone(), VariableInfo { linenum: line });
-                if let Some(init) = initializer {
-                    if (!fact) {
-                        self.c_code.push_str(&format!("{} = ", identifier));
-                        self.gen_ir_ast(*init);
-                        self.c_code.push_str(";\n");
-                    } else {
-                        //self.c_code.push_str(&format!("{}=", identifier));
-                        self.gen_ir_ast(*init);
-                        self.c_code.push_str(";\n");
-                    }
+                if var_type == Type::Str {
+                    self.require(STRING_RUNTIME);
+                }
+                //Declaration and initializer share one line so #line stays accurate
+                self.c_code.push_str(&format!(
+                    "{}{}; {} = ",
+                    Self::c_type(Some(&var_type)),
+                    c_name,
+                    c_name
+                ));
+                match initializer {
+                    Some(init) => self.gen_ir_ast(*init),
+                    None => self.c_code.push_str(Self::default_value(&var_type)),
                 }
+                self.c_code.push_str(";\n");
+                self.variables
+                    .declare(&identifier, VariableInfo { c_name, var_type });
             }
-            ast::ASTNode::DisplayStatement(identifier, _line) => {
+            ast::ASTNode::DisplayStatement(identifier, _span)
+            | ast::ASTNode::DisplayIntStatement(identifier, _span) => {
+                let c_name = self.c_name(&identifier);
+                //%g is what the interpreter imitates for floats
+                let format = match self.variables.get(&identifier) {
+                    Some(info) if info.var_type == Type::Float => "%g",
+                    _ => "%lld",
+                };
                 self.c_code
-                    .push_str(&format!("printf(\"%d\\n\", {});\n", identifier));
+                    .push_str(&format!("printf(\"{}\\n\", {});\n", format, c_name));
             }
-            ast::ASTNode::DisplayIntStatement(identifier, _line) => {
-                self.c_code
-                    .push_str(&format!("printf(\"%d\\n\", {});\n", identifier));
+            ast::ASTNode::DisplayStringStatement(indntifier, _span) => {
+                //Written with its length, like sco_str_print, so a \u{0} doesn't end it
+                let line = format!("{}\n", indntifier);
+                self.c_code.push_str(&format!(
+                    "fwrite(\"{}\", 1, {}, stdout);\n",
+                    escape_c(&line),
+                    line.len()
+                ));
             }
-            ast::ASTNode::DisplayStringStatement(indntifier, _line) => {
-                self.c_code
-                    .push_str(&format!("printf(\"%s\\n\", \"{}\");\n", indntifier));
+            ast::ASTNode::DisplayBoolStatement(identifier, _span) => {
+                let c_name = self.c_name(&identifier);
+                self.c_code.push_str(&format!(
+                    "printf(\"%s\\n\", {} ? \"TRUE\" : \"FALSE\");\n",
+                    c_name
+                ));
+            }
+            ast::ASTNode::AcceptIntStatement(identifier, span) => {
+                self.require_input();
+                let is_float = self
+                    .variables
+                    .get(&identifier)
+                    .is_some_and(|info| info.var_type == Type::Float);
+                let (parse, not_number) = if is_float {
+                    self.require(PARSE_FLOAT_HELPER);
+                    ("sco_parse_float", "ACCEPTnumeric needs a number")
+                } else {
+                    self.require(PARSE_INT_HELPER);
+                    (
+                        "sco_parse_int",
+                        "ACCEPTnumeric needs an integer in Int range",
+                    )
+                };
+                let line = self.temp("line");
+                let at_end = "ACCEPTnumeric reached the end of input";
+                //The variable only changes once the whole line has been read as a number
+                self.c_code.push_str(&format!(
+                    "{{ sco_str {l}; if (!sco_read_line(&{l})) {} \
+                     if (!{}({l}, &{})) {} sco_str_free({l}); }}\n",
+                    self.runtime_error("E0410", at_end, span),
+                    parse,
+                    self.c_name(&identifier),
+                    self.runtime_error("E0410", not_number, span),
+                    l = line
+                ));
             }
-            ast::ASTNode::DisplayStringVariable(v_n, _line) => {
+            ast::ASTNode::AcceptStringStatement(identifier, span) => {
+                self.require_input();
+                let line = self.temp("line");
+                let at_end = "ACCEPTstring reached the end of input";
+                self.c_code.push_str(&format!(
+                    "{{ sco_str {l}; if (!sco_read_line(&{l})) {} sco_str_set(&{}, {l}); }}\n",
+                    self.runtime_error("E0410", at_end, span),
+                    self.c_name(&identifier),
+                    l = line
+                ));
+            }
+            ast::ASTNode::DisplayStringVariable(v_n, _span) => {
+                let c_name = self.c_name(&v_n);
                 self.c_code
-                    .push_str(&format!("printf(\"%s\\n\", {});\n", v_n));
+                    .push_str(&format!("sco_str_print({});\n", c_name));
             }
 
             ast::ASTNode::ExpressionStatement {
                 expression,
                 identifier,
-                line,
+                ..
             } => {
-                self.c_code.push_str(&format!("{} =", identifier));
-                self.gen_ir_ast(*expression);
-                self.c_code.push_str(";\n");
+                let c_name = self.c_name(&identifier);
+                if self.is_string(&identifier) {
+                    self.c_code.push_str(&format!("sco_str_set(&{}, ", c_name));
+                    self.gen_ir_ast(*expression);
+                    self.c_code.push_str(");\n");
+                } else {
+                    self.c_code.push_str(&format!("{} =", c_name));
+                    self.gen_ir_ast(*expression);
+                    self.c_code.push_str(";\n");
+                }
             }
+            //Int operators that can divide by zero report it themselves, with the location
             ast::ASTNode::BinaryOp {
                 operator,
                 left,
                 right,
-                line,
+                span,
+            } if operator == "**"
+                || (matches!(operator.as_str(), "/" | "%")
+                    && self.expr_type(&node) == Type::Int) =>
+            {
+                let (helper, function) = match operator.as_str() {
+                    "**" => (IPOW_HELPER, "sco_ipow"),
+                    "/" => (IDIV_HELPER, "sco_idiv"),
+                    _ => (IMOD_HELPER, "sco_imod"),
+                };
+                self.require(helper);
+                self.c_code.push_str(&format!("{}(", function));
+                self.gen_ir_ast(*left);
+                self.c_code.push_str(", ");
+                self.gen_ir_ast(*right);
+                self.c_code.push_str(&format!(", {})", self.location(span)));
+            }
+            ast::ASTNode::BinaryOp {
+                operator,
+                left,
+                right,
+                ..
+            } if self.expr_type(&left) == Type::Str => {
+                self.require_string_ops();
+                let concat = operator == "+";
+                self.c_code.push_str(if concat {
+                    "sco_str_concat("
+                } else {
+                    "(sco_str_compare("
+                });
+                self.gen_ir_ast(*left);
+                self.c_code.push_str(", ");
+                self.gen_ir_ast(*right);
+                if concat {
+                    self.c_code.push(')');
+                } else {
+                    self.c_code.push_str(&format!(") {} 0)", operator));
+                }
+            }
+            //Int + - * wrap like the interpreter; going through unsigned keeps signed
+            //overflow, which C leaves undefined, out of the picture
+            ast::ASTNode::BinaryOp {
+                operator,
+                left,
+                right,
+                ..
+            } if matches!(operator.as_str(), "+" | "-" | "*")
+                && self.expr_type(&node) == Type::Int =>
+            {
+                self.c_code.push_str("(long long)((unsigned long long)");
+                self.gen_ir_ast(*left);
+                self.c_code
+                    .push_str(&format!(" {} (unsigned long long)", operator));
+                self.gen_ir_ast(*right);
+                self.c_code.push(')');
+            }
+            ast::ASTNode::BinaryOp {
+                operator,
+                left,
+                right,
+                ..
             } => {
                 self.c_code.push_str("(");
                 self.gen_ir_ast(*left);
-                self.c_code.push_str(&format!(" {} ", operator));
+                self.c_code
+                    .push_str(&format!(" {} ", Self::c_operator(&operator)));
                 self.gen_ir_ast(*right);
                 self.c_code.push_str(")");
             }
-            ast::ASTNode::Identifier(name, _line) => {
-                self.c_code.push_str(&name);
+            ast::ASTNode::UnaryOp { .. } if node.negated_literal() == Some(i64::MIN) => {
+                //9223372036854775808LL itself doesn't fit in a long long
+                self.c_code.push_str("(-9223372036854775807LL - 1)");
+            }
+            ast::ASTNode::UnaryOp { operand, .. }
+                if node.negated_literal().is_none() && self.expr_type(&node) == Type::Int =>
+            {
+                self.c_code
+                    .push_str("(long long)(0ULL - (unsigned long long)");
+                self.gen_ir_ast(*operand);
+                self.c_code.push(')');
+            }
+            ast::ASTNode::UnaryOp {
+                operator, operand, ..
+            } => {
+                self.c_code.push('(');
+                self.c_code.push_str(Self::c_operator(&operator));
+                self.gen_ir_ast(*operand);
+                self.c_code.push(')');
+            }
+            ast::ASTNode::Boolean(value, _span) => {
+                self.c_code.push_str(if value { "true" } else { "false" });
             }
-            ast::ASTNode::Number(value, _line) => {
-                self.c_code.push_str(&value.to_string());
+            ast::ASTNode::Identifier(name, _span) => {
+                let c_name = self.c_name(&name);
+                //A string read as a value is a copy, owned by whatever it's given to
+                if self.is_string(&name) {
+                    self.c_code.push_str(&format!("sco_str_copy({})", c_name));
+                } else {
+                    self.c_code.push_str(&c_name);
+                }
+            }
+            ast::ASTNode::Float(value, _span) => {
+                self.c_code.push_str(&value);
+            }
+            ast::ASTNode::Number(value, _span) => {
+                //The suffix keeps arithmetic on literals from overflowing a C int
+                self.c_code.push_str(&format!("{}LL", value));
             }
-            ast::ASTNode::StringLiteral(value, _line) => {
-                self.c_code.push_str(&format!("\"{}\"", value));
+            ast::ASTNode::StringLiteral(value, _span) => {
+                self.require(STRING_RUNTIME);
+                self.c_code.push_str(&format!(
+                    "sco_str_from(\"{}\", {})",
+                    escape_c(&value),
+                    value.len()
+                ));
             }
             ast::ASTNode::IfStatement {
                 condition,
                 then_block,
                 else_if_blocks,
                 else_block,
-                line,
+                ..
             } => {
-                self.c_code.push_str("if ");
+                self.c_code.push_str("if (");
                 self.gen_ir_ast(*condition);
-                self.c_code.push_str(" {\n");
+                self.c_code.push_str(") {\n");
 
                 // Generate the then block
-                for stmt in then_block {
-                    self.gen_ir_ast(stmt);
-                }
+                self.gen_block(then_block);
                 self.c_code.push_str("}\n");
 
                 for (elif_condition, elif_block) in else_if_blocks {
-                    self.c_code.push_str("else if ");
-                    self.gen_ir_ast(*elif_condition);
-                    self.c_code.push_str(" {\n");
-                    for stmt in elif_block {
-                        self.gen_ir_ast(stmt);
+                    if let Some(span) = elif_condition.span() {
+                        self.line_directive(span);
                     }
+                    self.c_code.push_str("else if (");
+                    self.gen_ir_ast(*elif_condition);
+                    self.c_code.push_str(") {\n");
+                    self.gen_block(elif_block);
                     self.c_code.push_str("}\n");
                 }
 
                 // Handle else block
                 if let Some(else_statements) = else_block {
                     self.c_code.push_str("else {\n");
-                    for stmt in else_statements {
-                        self.gen_ir_ast(stmt);
-                    }
+                    self.gen_block(else_statements);
                     self.c_code.push_str("}\n");
                 }
             }
+            ast::ASTNode::ForStatement {
+                variable,
+                start,
+                end,
+                step,
+                body,
+                span,
+            } => {
+                //Start, end and step are evaluated once, in that order, like the interpreter
+                let start_var = self.temp("start");
+                let end_var = self.temp("end");
+                let step_var = self.temp("step");
+                self.c_code.push_str(&format!("{{ long long {} = ", start_var));
+                self.gen_ir_ast(*start);
+                self.c_code.push_str(&format!("; long long {} = ", end_var));
+                self.gen_ir_ast(*end);
+                self.c_code.push_str(&format!("; long long {} = ", step_var));
+                match step {
+                    Some(step) => {
+                        let step_span = step.span().unwrap_or(span);
+                        self.gen_ir_ast(*step);
+                        self.c_code.push_str(&format!(
+                            "; if ({} == 0) {}\n",
+                            step_var,
+                            self.runtime_error("E0406", "FOR step must not be zero", step_span)
+                        ));
+                    }
+                    None => self.c_code.push_str("1;\n"),
+                }
+                let loop_var = self.declaration_name(&variable);
+                self.c_code.push_str(&format!(
+                    "for (long long {v} = {b}; {s} > 0 ? {v} <= {e} : {v} >= {e}; {v} += {s}) {{\n",
+                    b = start_var,
+                    v = loop_var,
+                    e = end_var,
+                    s = step_var
+                ));
+                self.variables.push();
+                self.variables.declare(
+                    &variable,
+                    VariableInfo {
+                        c_name: loop_var.clone(),
+                        var_type: Type::Int,
+                    },
+                );
+                self.gen_block(body);
+                self.variables.pop();
+                //Leave before the increment could step past the end (and overflow)
+                self.c_code.push_str(&format!(
+                    "if ({s} > 0 ? (unsigned long long){e} - (unsigned long long){v} \
+                     < (unsigned long long){s} : (unsigned long long){v} \
+                     - (unsigned long long){e} < 0ull - (unsigned long long){s}) \
+                     break;\n}}\n}}\n",
+                    v = loop_var,
+                    e = end_var,
+                    s = step_var
+                ));
+            }
+            ast::ASTNode::FunctionDeclaration { .. } => {
+                let signature = Self::function_signature(&node);
+                let ast::ASTNode::FunctionDeclaration {
+                    params,
+                    return_type,
+                    body,
+                    ..
+                } = node
+                else {
+                    unreachable!()
+                };
+                self.c_code.push_str(&format!("{} {{\n", signature));
+                //The body only sees the parameters, never main's variables
+                let outer = std::mem::take(&mut self.variables);
+                self.returns = return_type.clone();
+                for param in params {
+                    let info = VariableInfo {
+                        c_name: Self::variable_name(&param.name),
+                        var_type: param.var_type,
+                    };
+                    self.variables.declare(&param.name, info);
+                }
+                for stmt in body {
+                    self.gen_statement(stmt);
+                }
+                let frees = self.free_strings(self.variables.all());
+                self.c_code.push_str(&frees);
+                self.variables = outer;
+                //Falling off the end returns the type's default value
+                if let Some(return_type) = &return_type {
+                    self.c_code
+                        .push_str(&format!("return {};\n", Self::default_value(return_type)));
+                }
+                self.c_code.push_str("}\n\n");
+            }
+            ast::ASTNode::ReturnStatement { value, .. } => {
+                //Every string the function owns is freed on the way out; the value is
+                //computed first since it may read them
+                let frees = self.free_strings(self.variables.all());
+                match value {
+                    Some(value) if !frees.is_empty() => {
+                        let result = self.temp("result");
+                        self.c_code.push_str(&format!(
+                            "{{ {}{} = ",
+                            Self::c_type(self.returns.as_ref()),
+                            result
+                        ));
+                        self.gen_ir_ast(*value);
+                        self.c_code
+                            .push_str(&format!(";\n{}return {}; }}\n", frees, result));
+                    }
+                    Some(value) => {
+                        self.c_code.push_str("return ");
+                        self.gen_ir_ast(*value);
+                        self.c_code.push_str(";\n");
+                    }
+                    None => self.c_code.push_str(&format!("{{ {}return; }}\n", frees)),
+                }
+            }
+            ast::ASTNode::Call { name, args, span } => {
+                //Built-ins that can fail report the error themselves, so they also get
+                //told where they were called
+                let (function, helpers, reports_errors): (_, &[&'static str], _) =
+                    match name.as_str() {
+                        "LEN" => ("sco_str_len".to_string(), &[STRING_OPS], false),
+                        "MID" => ("sco_str_mid".to_string(), &[STRING_OPS], true),
+                        "STR" => ("sco_str_of_int".to_string(), &[STR_HELPER], false),
+                        "VAL" => (
+                            "sco_str_val".to_string(),
+                            &[PARSE_INT_HELPER, VAL_HELPER],
+                            true,
+                        ),
+                        _ => (format!("sco_fn_{}", name), &[], false),
+                    };
+                if builtins::lookup(&name).is_some() {
+                    self.require(STRING_RUNTIME);
+                }
+                for helper in helpers {
+                    self.require(helper);
+                }
+                self.c_code.push_str(&format!("{}(", function));
+                for (index, arg) in args.into_iter().enumerate() {
+                    if index > 0 {
+                        self.c_code.push_str(", ");
+                    }
+                    self.gen_ir_ast(arg);
+                }
+                if reports_errors {
+                    self.c_code.push_str(&format!(", {}", self.location(span)));
+                }
+                self.c_code.push(')');
+            }
+            ast::ASTNode::CallStatement { call, .. } => {
+                //A string result nobody keeps still has to be freed
+                let discards_string = self.expr_type(&call) == Type::Str;
+                if discards_string {
+                    self.c_code.push_str("sco_str_free(");
+                    self.gen_ir_ast(*call);
+                    self.c_code.push_str(");\n");
+                } else {
+                    self.gen_ir_ast(*call);
+                    self.c_code.push_str(";\n");
+                }
+            }
+            ast::ASTNode::WhileStatement {
+                condition, body, ..
+            } => {
+                self.c_code.push_str("while (");
+                self.gen_ir_ast(*condition);
+                self.c_code.push_str(") {\n");
+                self.gen_block(body);
+                self.c_code.push_str("}\n");
+            }
         }
     }
 
-    pub fn generate_c_file(&self) -> std::io::Result<()> {
-        let mut c_file = File::create("output/code.c")?;
+    pub fn diagnostics(&self) -> &[Diagnostic] {
+        &self.diagnostics
+    }
+
+    pub fn c_code(&self) -> &str {
+        &self.c_code
+    }
+
+    pub fn generate_c_file(&self, path: &Path) -> std::io::Result<()> {
+        let mut c_file = File::create(path)?;
         write!(c_file, "{}", self.c_code)?;
         Ok(())
     }
 }
+
+/// Text as it must appear between the quotes of a C string literal. Control characters
+/// become three-digit octal escapes, which can't swallow a following digit, and `??`
+/// is broken up so it can't start a trigraph.
+fn escape_c(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    let mut previous = None;
+    for ch in text.chars() {
+        match ch {
+            '\\' => escaped.push_str("\\\\"),
+            '"' => escaped.push_str("\\\""),
+            '\n' => escaped.push_str("\\n"),
+            '\t' => escaped.push_str("\\t"),
+            '?' if previous == Some('?') => escaped.push_str("\\?"),
+            ch if ch.is_ascii_control() => escaped.push_str(&format!("\\{:03o}", ch as u32)),
+            ch => escaped.push(ch),
+        }
+        previous = Some(ch);
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ast::Parser;
+    use crate::token::Lexer;
+
+    fn parse(program: &str) -> ast::ASTNode {
+        let mut lexer = Lexer::new();
+        lexer.tokenize(program);
+        let (ast, diagnostics) = Parser::new(lexer.return_tok()).parse();
+        assert!(diagnostics.is_empty(), "{:?}", diagnostics);
+        ast
+    }
+
+    fn generate(program: &str) -> String {
+        CodeGenerator::new().generate_ir(parse(program))
+    }
+
+    #[test]
+    fn if_conditions_are_parenthesized() {
+        let program = "VARbool a = TRUE;\nVARint n = 2;\n\
+                       IF (a) THEN\n  DISPLAYstring \"a\";\n\
+                       ELSE-IF (n) THEN\n  DISPLAYstring \"n\";\n\
+                       ELIF (n == 3) THEN\n  DISPLAYstring \"three\";\n\
+                       ELSE\n  DISPLAYstring \"none\";\n\
+                       END-IF\nEND\n";
+        let c_code = generate(program);
+        for expected in [
+            "if (sco_v_a) {",
+            "else if (sco_v_n) {",
+            "else if ((sco_v_n == 3LL)) {",
+            "else {",
+        ] {
+            assert!(c_code.contains(expected), "{} not in\n{}", expected, c_code);
+        }
+    }
+
+    #[test]
+    fn string_runtime_comes_before_string_ops() {
+        let c_code = generate("VARbool b = \"a\" < \"b\";\nEND\n");
+        let typedef = c_code
+            .find("} sco_str;")
+            .expect("string runtime is included");
+        let compare = c_code
+            .find("sco_str_compare(sco_str")
+            .expect("string ops are included");
+        assert!(typedef < compare, "{}", c_code);
+    }
+
+    #[test]
+    fn conversion_helpers_are_only_emitted_when_called() {
+        let c_code = generate("VARint n = LEN(\"abc\");\nEND\n");
+        for helper in ["sco_str_of_int(", "sco_parse_int(", "sco_str_val("] {
+            assert!(!c_code.contains(helper), "{}", c_code);
+        }
+        let c_code = generate("VARint n = VAL(STR(5));\nEND\n");
+        let parse = c_code.find("sco_parse_int(sco_str").expect("VAL parses");
+        let val = c_code.find("sco_str_val(sco_str").expect("VAL is included");
+        assert!(parse < val, "{}", c_code);
+        assert!(c_code.contains("sco_str_of_int(long long"), "{}", c_code);
+    }
+
+    #[test]
+    fn accept_numeric_parses_as_the_variable_type() {
+        let c_code = generate("VARfloat x;\nVARint n;\nACCEPTnumeric x;\nACCEPTnumeric n;\nEND\n");
+        for expected in [
+            "sco_parse_float(sco_line_1, &sco_v_x)",
+            "sco_parse_int(sco_line_2, &sco_v_n)",
+        ] {
+            assert!(c_code.contains(expected), "{} not in\n{}", expected, c_code);
+        }
+    }
+
+    #[test]
+    fn escape_c_keeps_text_intact_in_a_c_literal() {
+        assert_eq!(escape_c("a\"b\\c"), "a\\\"b\\\\c");
+        assert_eq!(escape_c("1\n2\t3"), "1\\n2\\t3");
+        //Octal escapes are always three digits, so a digit after one stays a digit
+        assert_eq!(escape_c("\u{0}1\u{7f}"), "\\0001\\177");
+        assert_eq!(escape_c("??=??"), "?\\?=?\\?");
+        assert_eq!(escape_c("é?"), "é?");
+    }
+
+    #[test]
+    fn display_string_writes_its_length() {
+        let c_code = generate("DISPLAYstring \"a\\u{0}b\";\nEND\n");
+        assert!(
+            c_code.contains("fwrite(\"a\\000b\\n\", 1, 4, stdout);"),
+            "{}",
+            c_code
+        );
+    }
+
+    #[test]
+    fn string_operators_lower_to_runtime_calls() {
+        let c_code = generate(
+            "VARstr a = \"x\";\nVARstr b = a + \"y\";\n\
+             VARbool less = a < b;\nVARint n = LEN(a) + 1;\nEND\n",
+        );
+        for expected in [
+            "sco_v_b = sco_str_concat(sco_str_copy(sco_v_a), sco_str_from(\"y\", 1));",
+            "sco_v_less = (sco_str_compare(sco_str_copy(sco_v_a), sco_str_copy(sco_v_b)) < 0);",
+            "sco_v_n = (long long)((unsigned long long)sco_str_len(sco_str_copy(sco_v_a)) \
+             + (unsigned long long)1LL);",
+        ] {
+            assert!(c_code.contains(expected), "{} not in\n{}", expected, c_code);
+        }
+    }
+
+    #[test]
+    fn user_functions_do_not_clash_with_runtime_helpers() {
+        let c_code = generate(
+            "FUNC str_len(VARstr s) RETURNS int\nRETURN 1;\nEND-FUNC\n\
+             VARint n = LEN(\"abc\") + str_len(\"x\");\nEND\n",
+        );
+        for expected in [
+            "long long sco_fn_str_len(sco_str sco_v_s);",
+            "sco_str_len(sco_str_from(\"abc\", 3)) \
+             + (unsigned long long)sco_fn_str_len(sco_str_from(\"x\", 1)));",
+        ] {
+            assert!(c_code.contains(expected), "{} not in\n{}", expected, c_code);
+        }
+    }
+
+    #[test]
+    fn variables_never_use_c_names_directly() {
+        let c_code = generate(
+            "FUNC twice(VARint free) RETURNS int\nRETURN free * 2;\nEND-FUNC\n\
+             VARint true = 0;\nVARint bool = twice(true);\n\
+             IF (bool > 0) THEN\nVARint bool = 7;\nEND-IF\nEND\n",
+        );
+        for expected in [
+            "long long sco_fn_twice(long long sco_v_free) {",
+            "long long sco_v_true; sco_v_true = 0LL;",
+            "long long sco_v_bool; sco_v_bool = sco_fn_twice(sco_v_true);",
+            //A shadowing declaration is numbered
+            "long long sco_v1_bool; sco_v1_bool = 7LL;",
+        ] {
+            assert!(c_code.contains(expected), "{} not in\n{}", expected, c_code);
+        }
+    }
+
+    #[test]
+    fn int_arithmetic_wraps_through_unsigned() {
+        let c_code = generate(
+            "VARint m = -9223372036854775808;\nVARint n = -m;\nVARint k = 9223372036854775807;\n\
+             k += 1;\nVARint p = m * -1;\nVARfloat f = -1.5 + m;\nEND\n",
+        );
+        for expected in [
+            "sco_v_m = (-9223372036854775807LL - 1);",
+            "sco_v_n = (long long)(0ULL - (unsigned long long)sco_v_m);",
+            "sco_v_k =(long long)((unsigned long long)sco_v_k + (unsigned long long)1LL);",
+            "sco_v_p = (long long)((unsigned long long)sco_v_m * (unsigned long long)(-1LL));",
+            "sco_v_f = ((-1.5) + sco_v_m);",
+        ] {
+            assert!(c_code.contains(expected), "{} not in\n{}", expected, c_code);
+        }
+    }
+
+    #[test]
+    fn int_division_goes_through_checked_helpers() {
+        let c_code = generate(
+            "VARint a = 7;\nVARint b = a / 2;\nVARint c = a % b;\n\
+             VARint d = a ** -1;\nVARfloat f = 1.5 / a;\nEND\n",
+        );
+        for expected in [
+            "sco_v_b = sco_idiv(sco_v_a, 2LL, \"<input>:2:12\");",
+            "sco_v_c = sco_imod(sco_v_a, sco_v_b, \"<input>:3:12\");",
+            "sco_v_d = sco_ipow(sco_v_a, (-1LL), \"<input>:4:12\");",
+            "sco_v_f = (1.5 / sco_v_a);",
+            "if (b == -1) return (long long)(0ULL - (unsigned long long)a);",
+        ] {
+            assert!(c_code.contains(expected), "{} not in\n{}", expected, c_code);
+        }
+    }
+
+    #[test]
+    fn strings_are_freed_when_replaced_or_dropped() {
+        let c_code = generate(
+            "FUNC pick(VARint n) RETURNS str\n\
+             VARstr outer = \"o\";\n\
+             IF (n > 0) THEN\nVARstr inner = \"i\";\nRETURN inner;\nEND-IF\n\
+             RETURN outer;\nEND-FUNC\n\
+             VARstr s;\ns = \"longer text\";\npick(1);\nEND\n",
+        );
+        for expected in [
+            //An uninitialized VARstr still owns a buffer, and assignment frees the old one
+            "sco_str sco_v_s; sco_v_s = sco_str_from(\"\", 0);",
+            "sco_str_set(&sco_v_s, sco_str_from(\"longer text\", 11));",
+            //RETURN from a nested block frees the strings of every enclosing block
+            "{ sco_str sco_result_1 = sco_str_copy(sco_v_inner);\n\
+             sco_str_free(sco_v_inner);\nsco_str_free(sco_v_outer);\nreturn sco_result_1; }",
+            "sco_str_free(sco_v_inner);\n}",
+            //A Str result that nobody keeps
+            "sco_str_free(sco_fn_pick(1LL));",
+            "sco_str_free(sco_v_s);\n    return 0;",
+        ] {
+            assert!(c_code.contains(expected), "{} not in\n{}", expected, c_code);
+        }
+    }
+
+    #[test]
+    fn for_bounds_are_evaluated_once_in_source_order() {
+        let c_code = generate(
+            "VARint s = 0;\nFOR i = 10 TO 1 STEP -2\n  DISPLAY i;\nEND-FOR\n\
+             FOR j = 1 TO 0 STEP s\n  DISPLAY j;\nEND-FOR\nEND\n",
+        );
+        for expected in [
+            //A negative step counts down, and an empty range fails the first test
+            "{ long long sco_start_1 = 10LL; long long sco_end_2 = 1LL; \
+             long long sco_step_3 = (-2LL); if (sco_step_3 == 0)",
+            "for (long long sco_v_i = sco_start_1; \
+             sco_step_3 > 0 ? sco_v_i <= sco_end_2 : sco_v_i >= sco_end_2; sco_v_i += sco_step_3) {",
+            "{ long long sco_start_4 = 1LL; long long sco_end_5 = 0LL; long long sco_step_6 = sco_v_s; \
+             if (sco_step_6 == 0) { fprintf(stderr, \"error[E0406]: FOR step must not be zero\\n \
+             --> <input>:5:21\\n\"); exit(1); }",
+        ] {
+            assert!(c_code.contains(expected), "{} not in\n{}", expected, c_code);
+        }
+    }
+}
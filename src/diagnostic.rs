@@ -0,0 +1,159 @@
+use std::fmt;
+
+use crate::token::Span;
+
+// Diagnostic codes, grouped by the stage that reports them:
+//   E00xx  lexer     E0001 unexpected character, E0002 unterminated string,
+//                    E0003 malformed word, E0004 unterminated block comment,
+//                    E0005 invalid escape sequence
+//   E01xx  parser    E0100 expected token, E0101 expected expression,
+//                    E0102 bad DISPLAYstring operand, E0103 stray END-IF/ELSE,
+//                    E0104 missing END, E0105 nested FUNC, E0106 unknown type name
+//   E02xx  sema      E0200 undeclared variable, E0201 duplicate declaration,
+//                    E0202 assignment type mismatch, E0203 bad operand types,
+//                    E0204 DISPLAY type mismatch, E0205 non-numeric condition,
+//                    E0206 non-integer FOR bound, E0207 duplicate function,
+//                    E0208 RETURN outside FUNC, E0209 RETURN type mismatch,
+//                    E0210 unknown function, E0211 wrong arguments,
+//                    E0212 function without a value used in an expression,
+//                    E0213 numeric literal out of range, E0214 ACCEPT type mismatch
+//   E03xx  codegen   E0300 unknown variable type
+//   E04xx  runtime   E0400 undeclared variable, E0401 type mismatch,
+//                    E0402 division by zero, E0403 bad expression,
+//                    E0404 bad DISPLAY operand, E0405 output failure,
+//                    E0406 FOR step of zero, E0407 bad call,
+//                    E0408 call stack overflow, E0409 bad built-in argument,
+//                    E0410 bad or missing input
+//   W00xx  warnings  W0001 code after END, W0002 shadowed variable
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Severity::Error => write!(f, "error"),
+            Severity::Warning => write!(f, "warning"),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub code: &'static str,
+    pub message: String,
+    pub file: Option<String>,
+    pub span: Span,
+}
+
+impl Diagnostic {
+    pub fn new(severity: Severity, code: &'static str, message: impl Into<String>, span: Span) -> Self {
+        Diagnostic {
+            severity,
+            code,
+            message: message.into(),
+            file: None,
+            span,
+        }
+    }
+
+    pub fn error(code: &'static str, message: impl Into<String>, span: Span) -> Self {
+        Self::new(Severity::Error, code, message, span)
+    }
+
+    pub fn warning(code: &'static str, message: impl Into<String>, span: Span) -> Self {
+        Self::new(Severity::Warning, code, message, span)
+    }
+
+    pub fn with_file(mut self, file: impl Into<String>) -> Self {
+        self.file = Some(file.into());
+        self
+    }
+
+    pub fn is_error(&self) -> bool {
+        self.severity == Severity::Error
+    }
+
+    /// rustc-style rendering: header, location, the offending source line and carets
+    /// under the span.
+    pub fn render(&self, source: &str) -> String {
+        let mut out = format!("{}[{}]: {}\n", self.severity, self.code, self.message);
+        let file = self.file.as_deref().unwrap_or("<input>");
+
+        let start = self.span.start.min(source.len());
+        let line_start = source[..start].rfind('\n').map_or(0, |i| i + 1);
+        let line_text = source[line_start..].lines().next().unwrap_or("");
+        let line_end = line_start + line_text.len();
+        let column = source[line_start..start].chars().count() + 1;
+        out.push_str(&format!(" --> {}:{}:{}\n", file, self.span.line, column));
+
+        let gutter = " ".repeat(self.span.line.to_string().len());
+        let end = self.span.end.clamp(start, line_end.max(start));
+        let width = source[start..end].chars().count().max(1);
+        let padding: String = source[line_start..start]
+            .chars()
+            .map(|c| if c == '\t' { '\t' } else { ' ' })
+            .collect();
+
+        out.push_str(&format!("{} |\n", gutter));
+        out.push_str(&format!("{} | {}\n", self.span.line, line_text));
+        out.push_str(&format!("{} | {}{}\n", gutter, padding, "^".repeat(width)));
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Error on line 2 of `source`, over the bytes from `start` to `end`.
+    fn render(source: &str, start: usize, end: usize) -> String {
+        let span = Span {
+            line: 2,
+            column: 0,
+            start,
+            end,
+        };
+        Diagnostic::error("E0000", "test", span).render(source)
+    }
+
+    #[test]
+    fn columns_and_carets_count_characters() {
+        let source = "END\nVARstr s = \"é€\" + x;\n";
+        let start = source.find("\"é").unwrap();
+        let end = source.find(" +").unwrap();
+        assert_eq!(
+            render(source, start, end),
+            "error[E0000]: test\n --> <input>:2:12\n  |\n2 | VARstr s = \"é€\" + x;\n  |            ^^^^\n"
+        );
+        let x = source.find("x;").unwrap();
+        assert!(render(source, x, x + 1).contains(" --> <input>:2:19\n"));
+    }
+
+    #[test]
+    fn tabs_stay_in_the_caret_padding() {
+        let source = "END\n\tDISPLAY\ty;\n";
+        let y = source.find('y').unwrap();
+        assert_eq!(
+            render(source, y, y + 1),
+            "error[E0000]: test\n --> <input>:2:10\n  |\n2 | \tDISPLAY\ty;\n  | \t       \t^\n"
+        );
+    }
+
+    #[test]
+    fn empty_and_out_of_range_spans_get_one_caret() {
+        //A missing `;` points just past the end of the line
+        let source = "END\nVARint x = 1\n";
+        let end = source.len() - 1;
+        assert_eq!(
+            render(source, end, end),
+            "error[E0000]: test\n --> <input>:2:13\n  |\n2 | VARint x = 1\n  |             ^\n"
+        );
+        //A span past the end of the file is clamped to the empty last line
+        assert!(render(source, 100, 120).ends_with("2 | \n  | ^\n"));
+    }
+}
@@ -0,0 +1,1010 @@
+use crate::ast::{ASTNode, Parameter};
+use crate::builtins;
+use crate::diagnostic::Diagnostic;
+use crate::scope::ScopeStack;
+use crate::token::{Span, Type};
+
+use std::collections::HashMap;
+use std::fmt;
+use std::io::{self, BufRead, Write};
+use std::rc::Rc;
+use std::thread;
+
+/// Deepest call nesting before the interpreter reports a stack overflow instead of
+/// overflowing its own stack.
+const MAX_CALL_DEPTH: usize = 5000;
+
+/// Stack for the interpreter thread. Every nested call recurses through `execute` and
+/// `evaluate`, so `MAX_CALL_DEPTH` needs far more than the default main thread stack.
+const STACK_SIZE: usize = 512 * 1024 * 1024;
+
+/// Runs `f` on a thread with a stack big enough for `MAX_CALL_DEPTH` nested calls.
+pub fn with_large_stack<T: Send>(f: impl FnOnce() -> T + Send) -> T {
+    thread::scope(|scope| {
+        thread::Builder::new()
+            .stack_size(STACK_SIZE)
+            .spawn_scoped(scope, f)
+            .expect("failed to start the interpreter thread")
+            .join()
+            .unwrap_or_else(|panic| std::panic::resume_unwind(panic))
+    })
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Int(i64),
+    Float(f64),
+    Str(String),
+    Bool(bool),
+}
+
+impl Value {
+    fn type_of(&self) -> Type {
+        match self {
+            Value::Int(_) => Type::Int,
+            Value::Float(_) => Type::Float,
+            Value::Str(_) => Type::Str,
+            Value::Bool(_) => Type::Bool,
+        }
+    }
+
+    fn default_for(var_type: &Type) -> Value {
+        match var_type {
+            Type::Str => Value::Str(String::new()),
+            Type::Bool => Value::Bool(false),
+            Type::Float => Value::Float(0.0),
+            _ => Value::Int(0),
+        }
+    }
+
+    /// The value as stored in a `var_type` slot: an Int put into a Float becomes one.
+    fn widened(self, var_type: &Type) -> Value {
+        match (self, var_type) {
+            (Value::Int(n), Type::Float) => Value::Float(n as f64),
+            (value, _) => value,
+        }
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Int(n) => write!(f, "{}", n),
+            Value::Float(x) => write!(f, "{}", format_float(*x)),
+            Value::Str(s) => write!(f, "{}", s),
+            Value::Bool(true) => write!(f, "TRUE"),
+            Value::Bool(false) => write!(f, "FALSE"),
+        }
+    }
+}
+
+/// What a statement tells the enclosing block to do next.
+enum Flow {
+    Next,
+    Return(Option<Value>),
+}
+
+struct Function {
+    params: Vec<Parameter>,
+    return_type: Option<Type>,
+    body: Vec<ASTNode>,
+}
+
+/// Evaluates an `ASTNode::Program` directly, without going through C.
+/// Output matches what the generated C prints for the same program.
+pub struct Interpreter<'a> {
+    variables: ScopeStack<Value>,
+    functions: HashMap<String, Rc<Function>>,
+    depth: usize,
+    out: Box<dyn Write + 'a>,
+    //Where ACCEPT reads from; `None` is standard input, which the REPL shares
+    input: Option<Box<dyn BufRead + 'a>>,
+}
+
+impl<'a> Interpreter<'a> {
+    pub fn new() -> Self {
+        Self::with_output(io::stdout())
+    }
+
+    pub fn with_output<W: Write + 'a>(out: W) -> Self {
+        Interpreter {
+            variables: ScopeStack::new(),
+            functions: HashMap::new(),
+            depth: 0,
+            out: Box::new(out),
+            input: None,
+        }
+    }
+
+    #[cfg(test)]
+    fn with_input<R: BufRead + 'a>(mut self, input: R) -> Self {
+        self.input = Some(Box::new(input));
+        self
+    }
+
+    pub fn variables(&self) -> impl Iterator<Item = (&String, &Value)> {
+        self.variables.iter()
+    }
+
+    /// Runs a program or one REPL input. A runtime error undoes every declaration and
+    /// assignment the input made, so a session carries on from the state before it.
+    pub fn run(&mut self, node: &ASTNode) -> Result<(), Diagnostic> {
+        let variables = self.variables.clone();
+        let functions = self.functions.clone();
+        //Functions can be called before the line that declares them, as in the C output
+        if let ASTNode::Program(statements) = node {
+            for statement in statements {
+                if let ASTNode::FunctionDeclaration {
+                    name,
+                    params,
+                    return_type,
+                    body,
+                    ..
+                } = statement
+                {
+                    let function = Function {
+                        params: params.clone(),
+                        return_type: return_type.clone(),
+                        body: body.clone(),
+                    };
+                    self.functions.insert(name.clone(), Rc::new(function));
+                }
+            }
+        }
+        let result = self.execute(node);
+        if result.is_err() {
+            self.variables = variables;
+            self.functions = functions;
+        }
+        self.out
+            .flush()
+            .map_err(|e| output_error(e, Span::default()))?;
+        result.map(|_| ())
+    }
+
+    fn execute(&mut self, node: &ASTNode) -> Result<Flow, Diagnostic> {
+        match node {
+            ASTNode::Program(statements) => self.execute_block(statements),
+            ASTNode::VariableDeclaration {
+                identifier,
+                initializer,
+                span,
+                var_type,
+            } => {
+                let var_type = var_type.clone().unwrap_or(Type::Unknown);
+                let value = match initializer {
+                    Some(init) => {
+                        let value = self.evaluate(init)?;
+                        Self::check_type(identifier, &var_type, value, *span)?
+                    }
+                    None => Value::default_for(&var_type),
+                };
+                self.variables.declare(identifier, value);
+                Ok(Flow::Next)
+            }
+            ASTNode::ExpressionStatement {
+                expression,
+                identifier,
+                span,
+            } => {
+                let value = self.evaluate(expression)?;
+                self.assign(identifier, value, *span)
+            }
+            ASTNode::DisplayStatement(identifier, span)
+            | ASTNode::DisplayIntStatement(identifier, span) => {
+                match self.lookup(identifier, *span)? {
+                    value @ (Value::Int(_) | Value::Float(_)) => {
+                        self.print(&value.to_string(), *span)
+                    }
+                    other => Err(Self::display_error(identifier, &other, "a number", *span)),
+                }
+            }
+            ASTNode::DisplayStringStatement(text, span) => self.print(text, *span),
+            ASTNode::DisplayStringVariable(identifier, span) => {
+                match self.lookup(identifier, *span)? {
+                    Value::Str(s) => self.print(&s, *span),
+                    other => Err(Self::display_error(identifier, &other, "a string", *span)),
+                }
+            }
+            ASTNode::DisplayBoolStatement(identifier, span) => {
+                match self.lookup(identifier, *span)? {
+                    value @ Value::Bool(_) => self.print(&value.to_string(), *span),
+                    other => Err(Self::display_error(identifier, &other, "a boolean", *span)),
+                }
+            }
+            ASTNode::AcceptIntStatement(identifier, span) => {
+                let line = self.accept_line("ACCEPTnumeric", *span)?;
+                let value = match self.lookup(identifier, *span)? {
+                    Value::Float(_) => parse_float(&line)
+                        .map(Value::Float)
+                        .ok_or("ACCEPTnumeric needs a number"),
+                    _ => parse_int(&line)
+                        .map(Value::Int)
+                        .ok_or("ACCEPTnumeric needs an integer in Int range"),
+                }
+                .map_err(|message| Diagnostic::error("E0410", message, *span))?;
+                self.assign(identifier, value, *span)
+            }
+            ASTNode::AcceptStringStatement(identifier, span) => {
+                let line = self.accept_line("ACCEPTstring", *span)?;
+                self.assign(identifier, Value::Str(line), *span)
+            }
+            ASTNode::IfStatement {
+                condition,
+                then_block,
+                else_if_blocks,
+                else_block,
+                ..
+            } => {
+                if self.is_true(condition)? {
+                    return self.execute_scoped(then_block);
+                }
+                for (elif_condition, elif_block) in else_if_blocks {
+                    if self.is_true(elif_condition)? {
+                        return self.execute_scoped(elif_block);
+                    }
+                }
+                match else_block {
+                    Some(else_statements) => self.execute_scoped(else_statements),
+                    None => Ok(Flow::Next),
+                }
+            }
+            ASTNode::WhileStatement {
+                condition, body, ..
+            } => {
+                while self.is_true(condition)? {
+                    if let Flow::Return(value) = self.execute_scoped(body)? {
+                        return Ok(Flow::Return(value));
+                    }
+                }
+                Ok(Flow::Next)
+            }
+            ASTNode::ForStatement {
+                variable,
+                start,
+                end,
+                step,
+                body,
+                span,
+            } => {
+                let start = self.number(start)?;
+                let end = self.number(end)?;
+                let step = match step {
+                    Some(step) => {
+                        let value = self.number(step)?;
+                        if value == 0 {
+                            return Err(Diagnostic::error(
+                                "E0406",
+                                "FOR step must not be zero",
+                                step.span().unwrap_or(*span),
+                            ));
+                        }
+                        value
+                    }
+                    None => 1,
+                };
+
+                //The loop variable only exists for the duration of the loop
+                self.variables.push();
+                self.variables.declare(variable, Value::Int(start));
+                let result = self.run_for(variable, start, end, step, body, *span);
+                self.variables.pop();
+                result
+            }
+            // Registered up front by `run`
+            ASTNode::FunctionDeclaration { .. } => Ok(Flow::Next),
+            ASTNode::ReturnStatement { value, .. } => {
+                let value = match value {
+                    Some(value) => Some(self.evaluate(value)?),
+                    None => None,
+                };
+                Ok(Flow::Return(value))
+            }
+            ASTNode::CallStatement { call, .. } => self.evaluate_call(call).map(|_| Flow::Next),
+            // A bare expression has no effect on its own
+            ASTNode::BinaryOp { .. }
+            | ASTNode::UnaryOp { .. }
+            | ASTNode::Identifier(..)
+            | ASTNode::Number(..)
+            | ASTNode::Float(..)
+            | ASTNode::StringLiteral(..)
+            | ASTNode::Boolean(..)
+            | ASTNode::Call { .. } => self.evaluate(node).map(|_| Flow::Next),
+        }
+    }
+
+    fn run_for(
+        &mut self,
+        variable: &str,
+        start: i64,
+        end: i64,
+        step: i64,
+        body: &[ASTNode],
+        span: Span,
+    ) -> Result<Flow, Diagnostic> {
+        let in_range = |i: i64| if step > 0 { i <= end } else { i >= end };
+        let mut i = start;
+        while in_range(i) {
+            if let Some(slot) = self.variables.get_mut(variable) {
+                *slot = Value::Int(i);
+            }
+            if let Flow::Return(value) = self.execute_scoped(body)? {
+                return Ok(Flow::Return(value));
+            }
+            let Value::Int(current) = self.lookup(variable, span)? else {
+                unreachable!("loop variables are always Int")
+            };
+            //Stop instead of wrapping around when the next value would overflow
+            match current.checked_add(step) {
+                Some(next) => i = next,
+                None => break,
+            }
+        }
+        Ok(Flow::Next)
+    }
+
+    /// Runs a nested block in its own scope, which is dropped again even on error so
+    /// the REPL can carry on with a consistent state.
+    fn execute_scoped(&mut self, statements: &[ASTNode]) -> Result<Flow, Diagnostic> {
+        self.variables.push();
+        let result = self.execute_block(statements);
+        self.variables.pop();
+        result
+    }
+
+    fn execute_block(&mut self, statements: &[ASTNode]) -> Result<Flow, Diagnostic> {
+        for statement in statements {
+            if let Flow::Return(value) = self.execute(statement)? {
+                return Ok(Flow::Return(value));
+            }
+        }
+        Ok(Flow::Next)
+    }
+
+    /// Runs a call in a fresh frame holding only the parameters, like a C function.
+    /// Returns `None` for functions without RETURNS.
+    fn call(&mut self, name: &str, args: &[ASTNode], span: Span) -> Result<Option<Value>, Diagnostic> {
+        if builtins::lookup(name).is_some() {
+            let values = args
+                .iter()
+                .map(|arg| self.evaluate(arg))
+                .collect::<Result<Vec<_>, _>>()?;
+            return Self::builtin(name, values, span).map(Some);
+        }
+        let function = self.functions.get(name).cloned().ok_or_else(|| {
+            Diagnostic::error("E0407", format!("Unknown function '{}'", name), span)
+        })?;
+        if args.len() != function.params.len() {
+            return Err(Diagnostic::error(
+                "E0407",
+                format!(
+                    "Function '{}' takes {} argument(s) but {} were given",
+                    name,
+                    function.params.len(),
+                    args.len()
+                ),
+                span,
+            ));
+        }
+        if self.depth >= MAX_CALL_DEPTH {
+            return Err(Diagnostic::error(
+                "E0408",
+                format!("Call stack overflow: more than {} nested calls", MAX_CALL_DEPTH),
+                span,
+            ));
+        }
+
+        let mut frame = ScopeStack::new();
+        for (param, arg) in function.params.iter().zip(args) {
+            let value = self.evaluate(arg)?;
+            let value =
+                Self::check_type(&param.name, &param.var_type, value, arg.span().unwrap_or(span))?;
+            frame.declare(&param.name, value);
+        }
+
+        let caller = std::mem::replace(&mut self.variables, frame);
+        self.depth += 1;
+        let result = self.execute_block(&function.body);
+        self.depth -= 1;
+        self.variables = caller;
+
+        match result? {
+            Flow::Return(Some(value)) => match &function.return_type {
+                Some(return_type) => Ok(Some(value.widened(return_type))),
+                None => Ok(Some(value)),
+            },
+            //Falling off the end returns the type's default value, as in the C output
+            _ => Ok(function.return_type.as_ref().map(Value::default_for)),
+        }
+    }
+
+    /// Runs a built-in function on already evaluated arguments. Strings are counted in
+    /// characters, like the C runtime does.
+    fn builtin(name: &str, args: Vec<Value>, span: Span) -> Result<Value, Diagnostic> {
+        match (name, args.as_slice()) {
+            ("LEN", [Value::Str(s)]) => Ok(Value::Int(s.chars().count() as i64)),
+            ("MID", [Value::Str(s), Value::Int(start), Value::Int(count)]) => {
+                if *start < 1 || *count < 0 {
+                    return Err(Diagnostic::error(
+                        "E0409",
+                        "MID needs a start of at least 1 and a count of at least 0",
+                        span,
+                    ));
+                }
+                let mid = s.chars().skip(*start as usize - 1).take(*count as usize);
+                Ok(Value::Str(mid.collect()))
+            }
+            ("STR", [Value::Int(n)]) => Ok(Value::Str(n.to_string())),
+            ("VAL", [Value::Str(s)]) => parse_int(s).map(Value::Int).ok_or_else(|| {
+                Diagnostic::error(
+                    "E0409",
+                    "VAL needs the text of an integer in Int range",
+                    span,
+                )
+            }),
+            _ => Err(Diagnostic::error(
+                "E0407",
+                format!("Wrong arguments for built-in function '{}'", name),
+                span,
+            )),
+        }
+    }
+
+    fn evaluate_call(&mut self, call: &ASTNode) -> Result<Option<Value>, Diagnostic> {
+        match call {
+            ASTNode::Call { name, args, span } => self.call(name, args, *span),
+            _ => self.evaluate(call).map(Some),
+        }
+    }
+
+    fn evaluate(&mut self, node: &ASTNode) -> Result<Value, Diagnostic> {
+        match node {
+            ASTNode::Number(value, span) => value.parse::<i64>().map(Value::Int).map_err(|_| {
+                Diagnostic::error(
+                    "E0403",
+                    format!("Invalid integer literal '{}'", value),
+                    *span,
+                )
+            }),
+            ASTNode::Float(value, span) => value.parse::<f64>().map(Value::Float).map_err(|_| {
+                Diagnostic::error(
+                    "E0403",
+                    format!("Invalid float literal '{}'", value),
+                    *span,
+                )
+            }),
+            ASTNode::StringLiteral(value, _span) => Ok(Value::Str(value.clone())),
+            ASTNode::Boolean(value, _span) => Ok(Value::Bool(*value)),
+            ASTNode::Identifier(name, span) => self.lookup(name, *span),
+            ASTNode::Call { name, args, span } => {
+                self.call(name, args, *span)?.ok_or_else(|| {
+                    Diagnostic::error(
+                        "E0403",
+                        format!("Function '{}' does not return a value", name),
+                        *span,
+                    )
+                })
+            }
+            ASTNode::BinaryOp {
+                operator,
+                left,
+                right,
+                span,
+            } => {
+                //AND/OR only evaluate the right side when it can change the result
+                if operator == "AND" || operator == "OR" {
+                    let l = self.boolean(left)?;
+                    let value = match (operator.as_str(), l) {
+                        ("AND", false) => false,
+                        ("OR", true) => true,
+                        _ => self.boolean(right)?,
+                    };
+                    return Ok(Value::Bool(value));
+                }
+                let left = self.evaluate(left)?;
+                let right = self.evaluate(right)?;
+                match (left, right) {
+                    (Value::Int(l), Value::Int(r)) => Self::int_op(operator, l, r, *span),
+                    //Mixed arithmetic promotes the Int side
+                    (Value::Float(l), Value::Float(r)) => Self::float_op(operator, l, r, *span),
+                    (Value::Int(l), Value::Float(r)) => Self::float_op(operator, l as f64, r, *span),
+                    (Value::Float(l), Value::Int(r)) => Self::float_op(operator, l, r as f64, *span),
+                    (Value::Bool(l), Value::Bool(r)) if operator == "==" => Ok(Value::Bool(l == r)),
+                    (Value::Bool(l), Value::Bool(r)) if operator == "!=" => Ok(Value::Bool(l != r)),
+                    (Value::Str(l), Value::Str(r)) => Self::str_op(operator, l, r, *span),
+                    (l, r) => Err(Diagnostic::error(
+                        "E0401",
+                        format!(
+                            "Operator '{}' is not supported between {} and {}",
+                            operator,
+                            l.type_of(),
+                            r.type_of()
+                        ),
+                        *span,
+                    )),
+                }
+            }
+            ASTNode::UnaryOp {
+                operator,
+                operand,
+                span,
+            } => match operator.as_str() {
+                "NOT" => Ok(Value::Bool(!self.boolean(operand)?)),
+                "-" => match node.negated_literal() {
+                    Some(value) => Ok(Value::Int(value)),
+                    None => match self.evaluate(operand)? {
+                        Value::Int(n) => Ok(Value::Int(n.wrapping_neg())),
+                        Value::Float(x) => Ok(Value::Float(-x)),
+                        _ => Err(Diagnostic::error("E0401", "Expected a number", *span)),
+                    },
+                },
+                _ => Err(Diagnostic::error(
+                    "E0403",
+                    format!("Unknown operator '{}'", operator),
+                    *span,
+                )),
+            },
+            _ => Err(Diagnostic::error(
+                "E0403",
+                "Cannot evaluate this statement as an expression",
+                node.span().unwrap_or_default(),
+            )),
+        }
+    }
+
+    fn int_op(operator: &str, l: i64, r: i64, span: Span) -> Result<Value, Diagnostic> {
+        let result = match operator {
+            "+" => Value::Int(l.wrapping_add(r)),
+            "-" => Value::Int(l.wrapping_sub(r)),
+            "*" => Value::Int(l.wrapping_mul(r)),
+            "/" => {
+                if r == 0 {
+                    return Err(Diagnostic::error("E0402", "Division by zero", span));
+                }
+                Value::Int(l.wrapping_div(r))
+            }
+            "%" => {
+                if r == 0 {
+                    return Err(Diagnostic::error("E0402", "Division by zero", span));
+                }
+                Value::Int(l.wrapping_rem(r))
+            }
+            "**" => match Self::power(l, r) {
+                Some(result) => Value::Int(result),
+                None => return Err(Diagnostic::error("E0402", "Division by zero", span)),
+            },
+            "<" => Value::Bool(l < r),
+            "<=" => Value::Bool(l <= r),
+            ">" => Value::Bool(l > r),
+            ">=" => Value::Bool(l >= r),
+            "==" => Value::Bool(l == r),
+            "!=" => Value::Bool(l != r),
+            _ => {
+                return Err(Diagnostic::error(
+                    "E0403",
+                    format!("Unknown operator '{}'", operator),
+                    span,
+                ))
+            }
+        };
+        Ok(result)
+    }
+
+    //Division by zero gives an infinity or NaN, as in C
+    fn float_op(operator: &str, l: f64, r: f64, span: Span) -> Result<Value, Diagnostic> {
+        let result = match operator {
+            "+" => Value::Float(l + r),
+            "-" => Value::Float(l - r),
+            "*" => Value::Float(l * r),
+            "/" => Value::Float(l / r),
+            "<" => Value::Bool(l < r),
+            "<=" => Value::Bool(l <= r),
+            ">" => Value::Bool(l > r),
+            ">=" => Value::Bool(l >= r),
+            "==" => Value::Bool(l == r),
+            "!=" => Value::Bool(l != r),
+            _ => {
+                return Err(Diagnostic::error(
+                    "E0403",
+                    format!("Operator '{}' is not supported on Float", operator),
+                    span,
+                ))
+            }
+        };
+        Ok(result)
+    }
+
+    //Strings order byte by byte, which is how the C runtime compares them too
+    fn str_op(operator: &str, l: String, r: String, span: Span) -> Result<Value, Diagnostic> {
+        let result = match operator {
+            "+" => Value::Str(l + &r),
+            "<" => Value::Bool(l < r),
+            "<=" => Value::Bool(l <= r),
+            ">" => Value::Bool(l > r),
+            ">=" => Value::Bool(l >= r),
+            "==" => Value::Bool(l == r),
+            "!=" => Value::Bool(l != r),
+            _ => {
+                return Err(Diagnostic::error(
+                    "E0403",
+                    format!("Operator '{}' is not supported on Str", operator),
+                    span,
+                ))
+            }
+        };
+        Ok(result)
+    }
+
+    /// Integer power, wrapping like the other operators. A negative exponent means
+    /// 1 / base ** -exp truncated toward zero, so only 1 and -1 give nonzero results
+    /// and a zero base divides by zero (`None`).
+    fn power(base: i64, exp: i64) -> Option<i64> {
+        if exp < 0 {
+            return match base {
+                0 => None,
+                1 => Some(1),
+                -1 => Some(if exp % 2 == 0 { 1 } else { -1 }),
+                _ => Some(0),
+            };
+        }
+        let (mut result, mut base, mut exp) = (1i64, base, exp);
+        while exp > 0 {
+            if exp & 1 == 1 {
+                result = result.wrapping_mul(base);
+            }
+            base = base.wrapping_mul(base);
+            exp >>= 1;
+        }
+        Some(result)
+    }
+
+    fn number(&mut self, node: &ASTNode) -> Result<i64, Diagnostic> {
+        match self.evaluate(node)? {
+            Value::Int(n) => Ok(n),
+            _ => Err(Diagnostic::error(
+                "E0401",
+                "Expected a number",
+                node.span().unwrap_or_default(),
+            )),
+        }
+    }
+
+    fn boolean(&mut self, node: &ASTNode) -> Result<bool, Diagnostic> {
+        match self.evaluate(node)? {
+            Value::Bool(b) => Ok(b),
+            _ => Err(Diagnostic::error(
+                "E0401",
+                "Expected a boolean",
+                node.span().unwrap_or_default(),
+            )),
+        }
+    }
+
+    fn is_true(&mut self, condition: &ASTNode) -> Result<bool, Diagnostic> {
+        match self.evaluate(condition)? {
+            Value::Int(n) => Ok(n != 0),
+            Value::Float(x) => Ok(x != 0.0),
+            Value::Bool(b) => Ok(b),
+            Value::Str(_) => Err(Diagnostic::error(
+                "E0401",
+                "Condition must be a boolean or a number",
+                condition.span().unwrap_or_default(),
+            )),
+        }
+    }
+
+    fn display_error(name: &str, value: &Value, shown_as: &str, span: Span) -> Diagnostic {
+        Diagnostic::error(
+            "E0404",
+            format!(
+                "Cannot display {} variable '{}' as {}",
+                value.type_of().to_string().to_lowercase(),
+                name,
+                shown_as
+            ),
+            span,
+        )
+    }
+
+    fn lookup(&self, name: &str, span: Span) -> Result<Value, Diagnostic> {
+        self.variables.get(name).cloned().ok_or_else(|| {
+            Diagnostic::error("E0400", format!("Undeclared variable '{}'", name), span)
+        })
+    }
+
+    /// `value` as stored in a variable of type `expected`, widened if need be.
+    fn check_type(name: &str, expected: &Type, value: Value, span: Span) -> Result<Value, Diagnostic> {
+        let value = value.widened(expected);
+        if *expected == Type::Unknown || value.type_of() == *expected {
+            Ok(value)
+        } else {
+            Err(Diagnostic::error(
+                "E0401",
+                format!(
+                    "Cannot assign {} value to {} variable '{}'",
+                    value.type_of(),
+                    expected,
+                    name
+                ),
+                span,
+            ))
+        }
+    }
+
+    fn assign(&mut self, identifier: &str, value: Value, span: Span) -> Result<Flow, Diagnostic> {
+        let current = self.lookup(identifier, span)?;
+        let value = Self::check_type(identifier, &current.type_of(), value, span)?;
+        if let Some(slot) = self.variables.get_mut(identifier) {
+            *slot = value;
+        }
+        Ok(Flow::Next)
+    }
+
+    /// Next input line for `statement`, without its line ending. Running out of input
+    /// is an error, as in the C output.
+    fn accept_line(&mut self, statement: &str, span: Span) -> Result<String, Diagnostic> {
+        //Anything printed so far, such as a prompt, shows before waiting for input
+        self.out.flush().map_err(|e| output_error(e, span))?;
+        let mut line = String::new();
+        let read = match &mut self.input {
+            Some(input) => input.read_line(&mut line),
+            None => io::stdin().read_line(&mut line),
+        };
+        match read {
+            Ok(0) => Err(Diagnostic::error(
+                "E0410",
+                format!("{} reached the end of input", statement),
+                span,
+            )),
+            Ok(_) => {
+                if line.ends_with('\n') {
+                    line.pop();
+                    if line.ends_with('\r') {
+                        line.pop();
+                    }
+                }
+                Ok(line)
+            }
+            Err(e) => Err(Diagnostic::error(
+                "E0410",
+                format!("Failed to read input: {}", e),
+                span,
+            )),
+        }
+    }
+
+    fn print(&mut self, text: &str, span: Span) -> Result<Flow, Diagnostic> {
+        writeln!(self.out, "{}", text).map_err(|e| output_error(e, span))?;
+        Ok(Flow::Next)
+    }
+}
+
+/// Formats like C's `printf("%g")`, which the generated code uses: six significant
+/// digits, trailing zeros dropped, and an exponent outside 1e-4..1e6.
+fn format_float(x: f64) -> String {
+    if x.is_nan() {
+        return if x.is_sign_negative() { "-nan" } else { "nan" }.to_string();
+    }
+    if x.is_infinite() {
+        return if x < 0.0 { "-inf" } else { "inf" }.to_string();
+    }
+    let scientific = format!("{:.5e}", x);
+    let (mantissa, exponent) = scientific.split_once('e').expect("{:e} has an exponent");
+    let exponent: i32 = exponent.parse().expect("{:e} exponent is an integer");
+    let trim = |digits: &str| {
+        if digits.contains('.') {
+            digits.trim_end_matches('0').trim_end_matches('.').to_string()
+        } else {
+            digits.to_string()
+        }
+    };
+    if (-4..6).contains(&exponent) {
+        trim(&format!("{:.*}", (5 - exponent) as usize, x))
+    } else {
+        let sign = if exponent < 0 { '-' } else { '+' };
+        format!("{}e{}{:02}", trim(mantissa), sign, exponent.abs())
+    }
+}
+
+/// Integer text as VAL and ACCEPTnumeric read it: optionally signed digits, with only
+/// spaces and tabs around them.
+fn parse_int(text: &str) -> Option<i64> {
+    text.trim_matches([' ', '\t']).parse().ok()
+}
+
+/// Number text as ACCEPTnumeric reads it into a Float: decimal, with an optional
+/// exponent, or inf or nan, with only spaces and tabs around it.
+fn parse_float(text: &str) -> Option<f64> {
+    text.trim_matches([' ', '\t']).parse().ok()
+}
+
+fn output_error(e: io::Error, span: Span) -> Diagnostic {
+    Diagnostic::error("E0405", format!("Failed to write output: {}", e), span)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ast::Parser;
+    use crate::token::Lexer;
+
+    fn parse(program: &str) -> ASTNode {
+        let mut lexer = Lexer::new();
+        lexer.tokenize(program);
+        let (ast, diagnostics) = Parser::new(lexer.return_tok()).parse();
+        assert!(diagnostics.is_empty(), "{:?}", diagnostics);
+        ast
+    }
+
+    fn run(program: &str) -> String {
+        let mut out = Vec::new();
+        Interpreter::with_output(&mut out)
+            .run(&parse(program))
+            .expect("program runs");
+        String::from_utf8(out).expect("output is UTF-8")
+    }
+
+    #[test]
+    fn else_if_takes_the_first_true_branch() {
+        let cases = [(95, "A"), (90, "A"), (85, "B"), (72, "C"), (70, "C"), (12, "F")];
+        for (score, expected) in cases {
+            let output = run(&format!(
+                "VARint score = {};\n\
+                 IF (score >= 90) THEN\n  DISPLAYstring \"A\";\n\
+                 ELSE-IF (score >= 80) THEN\n  DISPLAYstring \"B\";\n\
+                 ELIF (score >= 70) THEN\n  DISPLAYstring \"C\";\n\
+                 ELSE\n  DISPLAYstring \"F\";\n\
+                 END-IF\nEND\n",
+                score
+            ));
+            assert_eq!(output, format!("{}\n", expected), "score {}", score);
+        }
+    }
+
+    #[test]
+    fn else_if_without_else_can_run_nothing() {
+        let program = "VARint n = 5;\n\
+                       IF (n == 1) THEN\n  DISPLAYstring \"one\";\n\
+                       ELIF (n == 2) THEN\n  DISPLAYstring \"two\";\n\
+                       END-IF\nDISPLAYstring \"done\";\nEND\n";
+        assert_eq!(run(program), "done\n");
+    }
+
+    #[test]
+    fn string_operators_and_builtins() {
+        let program = "VARstr s = \"h\u{e9}llo\" + \" world\";\n\
+                       DISPLAYstring s;\n\
+                       VARint n = LEN(s);\nDISPLAY n;\n\
+                       VARstr m = MID(s, 2, 4);\nDISPLAYstring m;\n\
+                       VARstr tail = MID(s, 9, 100);\nDISPLAYstring tail;\n\
+                       VARbool less = \"abc\" < \"abd\" AND \"ab\" < \"abc\";\n\
+                       DISPLAYbool less;\nEND\n";
+        assert_eq!(run(program), "h\u{e9}llo world\n11\n\u{e9}llo\nrld\nTRUE\n");
+    }
+
+    #[test]
+    fn str_and_val_convert_between_int_and_text() {
+        let program = "VARstr s = STR(-42) + \"!\";\nDISPLAYstring s;\n\
+                       VARint n = VAL(\" +17\\t\") * 2;\nDISPLAY n;\nEND\n";
+        assert_eq!(run(program), "-42!\n34\n");
+
+        let ast = parse("VARint n = VAL(\"12abc\");\nEND\n");
+        let error = Interpreter::with_output(Vec::new()).run(&ast).unwrap_err();
+        assert_eq!(error.code, "E0409");
+    }
+
+    #[test]
+    fn accept_reads_lines_and_reports_bad_input() {
+        let program = "VARstr name;\nVARint n;\nACCEPTstring name;\nACCEPTnumeric n;\n\
+                       DISPLAYstring name;\nDISPLAY n;\nACCEPTnumeric n;\nEND\n";
+        let ast = parse(program);
+        for (input, output, code) in [
+            ("Ada\r\n 42\nx\n", "Ada\n42\n", "E0410"),
+            ("Ada\n42\n", "Ada\n42\n", "E0410"),
+            ("Ada\n42\n7", "Ada\n42\n", ""),
+        ] {
+            let mut out = Vec::new();
+            let result = Interpreter::with_output(&mut out)
+                .with_input(input.as_bytes())
+                .run(&ast);
+            let error = result.err().map_or("", |e| e.code);
+            assert_eq!(error, code, "input {:?}", input);
+            assert_eq!(String::from_utf8(out).unwrap(), output, "input {:?}", input);
+        }
+    }
+
+    #[test]
+    fn accept_numeric_reads_a_float_into_a_float_variable() {
+        let ast = parse("VARfloat x;\nACCEPTnumeric x;\nDISPLAY x;\nEND\n");
+        for (input, output, code) in [
+            (" 2.5\t\n", "2.5\n", ""),
+            ("-1e3\n", "-1000\n", ""),
+            ("7\n", "7\n", ""),
+            ("0x10\n", "", "E0410"),
+            ("1.5.5\n", "", "E0410"),
+        ] {
+            let mut out = Vec::new();
+            let result = Interpreter::with_output(&mut out)
+                .with_input(input.as_bytes())
+                .run(&ast);
+            let error = result.err().map_or("", |e| e.code);
+            assert_eq!(error, code, "input {:?}", input);
+            assert_eq!(String::from_utf8(out).unwrap(), output, "input {:?}", input);
+        }
+    }
+
+    #[test]
+    fn a_failed_run_leaves_earlier_state_untouched() {
+        let mut out = Vec::new();
+        let mut interpreter = Interpreter::with_output(&mut out);
+        interpreter.run(&parse("VARint z = 0;\nEND\n")).unwrap();
+        let error = interpreter
+            .run(&parse("z = 5;\nVARint x = 1 / (z - 5);\nEND\n"))
+            .unwrap_err();
+        assert_eq!(error.code, "E0402");
+        let names: Vec<_> = interpreter.variables().map(|(name, _)| name).collect();
+        assert_eq!(names, ["z"]);
+        let retry = parse("VARint x = 2;\nDISPLAY x;\nDISPLAY z;\nEND\n");
+        interpreter.run(&retry).unwrap();
+        drop(interpreter);
+        assert_eq!(String::from_utf8(out).unwrap(), "2\n0\n");
+    }
+
+    #[test]
+    fn int_arithmetic_wraps_at_i64_min_and_max() {
+        let program = "VARint m = -9223372036854775808;\nDISPLAY m;\nVARint n = -m;\nDISPLAY n;\n\
+                       VARint k = 9223372036854775807;\nk += 1;\nDISPLAY k;\nEND\n";
+        assert_eq!(
+            run(program),
+            "-9223372036854775808\n-9223372036854775808\n-9223372036854775808\n"
+        );
+    }
+
+    #[test]
+    fn floats_format_like_printf_g() {
+        //Expected text is what printf("%g") prints for the same double
+        for (value, expected) in [
+            (0.0, "0"),
+            (-0.0, "-0"),
+            (1.5, "1.5"),
+            (-42.0, "-42"),
+            (0.1 + 0.2, "0.3"),
+            (123456.7, "123457"),
+            (100000.0, "100000"),
+            (999999.4, "999999"),
+            (999999.5, "1e+06"),
+            (1e6, "1e+06"),
+            (1234567.0, "1.23457e+06"),
+            (9.9999949, "9.99999"),
+            (9.9999951, "10"),
+            (0.0001, "0.0001"),
+            (0.00009999995, "0.0001"),
+            (0.00012345678, "0.000123457"),
+            (1e-5, "1e-05"),
+            (1e100, "1e+100"),
+            (2.5e-300, "2.5e-300"),
+            (5e-324, "4.94066e-324"),
+            (f64::INFINITY, "inf"),
+            (f64::NEG_INFINITY, "-inf"),
+            (f64::NAN, "nan"),
+            (-f64::NAN, "-nan"),
+        ] {
+            assert_eq!(format_float(value), expected, "{:e}", value);
+        }
+    }
+
+    #[test]
+    fn for_counts_down_skips_empty_ranges_and_rejects_step_zero() {
+        let program = "FOR i = 10 TO 1 STEP -2\n  DISPLAY i;\nEND-FOR\n\
+                       FOR j = 1 TO 0\n  DISPLAY j;\nEND-FOR\n\
+                       FOR k = 0 TO 1 STEP -1\n  DISPLAY k;\nEND-FOR\n\
+                       DISPLAYstring \"done\";\nEND\n";
+        assert_eq!(run(program), "10\n8\n6\n4\n2\ndone\n");
+
+        let ast = parse("VARint s = 0;\nFOR i = 1 TO 3 STEP s\n  DISPLAY i;\nEND-FOR\nEND\n");
+        let mut out = Vec::new();
+        let error = Interpreter::with_output(&mut out).run(&ast).unwrap_err();
+        assert_eq!(error.code, "E0406");
+        assert!(out.is_empty());
+    }
+}
@@ -1,55 +1,190 @@
 mod ast;
+mod builtins;
+mod cli;
 mod codegen;
+mod diagnostic;
+mod interp;
+mod repl;
+mod scope;
+mod sema;
 mod token;
+mod toolchain;
+use std::env;
 use std::fs;
-use std::io::Write;
+use std::io::{self, Write};
+use std::process;
+
+use cli::{Command, Emit, Options, Output, Parsed};
+use diagnostic::Diagnostic;
+use toolchain::CompilerConfig;
 
-use ast::ASTNode;
 fn main() {
-    let program = fs::read_to_string("tests/testprogram.sco");
-    match program {
-        Ok(contents) => {
-            let mut lexer = token::Lexer::new();
-            lexer.tokenize(&contents);
-            let tokens = lexer.return_tok();
-            match fs::File::create("tokendump.txt") {
-                Ok(mut file) => {
-                    if let Err(e) = writeln!(file, "{:?}", tokens) {
-                        eprintln!("Failed to write to file: {}", e);
-                    }
-                }
-                Err(e) => eprintln!("Failed to create file: {}", e),
-            }
-            //println!("{:?}", tokens);
-            let mut parser = ast::Parser::new(tokens);
-
-            let val = parser.parse(); //Result returned here
-
-            let printnodes = |node: &ASTNode| {
-                println!("{:?}", node);
-            };
-            match val {
-                Ok(asts) => {
-                    let ast_pretty = asts.clone(); //should be small enough to get away with a clone for now. Could use arc, but obnoxious refactor. Possibly if preformance tanks later.
-                    asts.traverse(&printnodes);
-                    let mut code_gen = codegen::CodeGenerator::new();
-                    
-                    code_gen.generate_ir(ast_pretty);
-                    match code_gen.generate_c_file() {
-                        Ok(o) => println!("C source file generated!"),
-                        Err(e) => println!(" C source file gen failed with error {e}"),
-                    };
-                }
-
-                Err(e) => {
-                    println!("Error returning AST {}",e);
-                }
+    let options = match cli::parse_args(env::args().skip(1)) {
+        Ok(Parsed::Run(options)) => options,
+        Ok(Parsed::Repl) => {
+            if let Err(e) = interp::with_large_stack(|| repl::Repl::new().run()) {
+                eprintln!("error: {}", e);
+                process::exit(1);
             }
+            return;
+        }
+        Ok(Parsed::Help) => {
+            println!("{}", cli::USAGE);
+            return;
+        }
+        Err(e) => {
+            eprintln!("error: {}\n\n{}", e, cli::USAGE);
+            process::exit(2);
+        }
+    };
+
+    let result = match options.command {
+        Command::Compile => compile(&options).map(|_| 0),
+        Command::Build => build(&options).map(|_| 0),
+        Command::Run => run(&options),
+        Command::Interp => interpret(&options).map(|_| 0),
+    };
+    match result {
+        Ok(code) => process::exit(code),
+        Err(e) => {
+            eprintln!("error: {}", e);
+            process::exit(1);
+        }
+    }
+}
+
+struct Source {
+    name: String,
+    text: String,
+}
 
-            
-            
+fn read_source(options: &Options) -> Result<Source, String> {
+    let text = fs::read_to_string(&options.input)
+        .map_err(|e| format!("Failed to read {}: {}", options.input.display(), e))?;
+    Ok(Source {
+        name: options.input.display().to_string(),
+        text,
+    })
+}
+
+/// Prints every diagnostic against the source; fails if any of them is an error.
+fn report(source: &Source, diagnostics: &[Diagnostic]) -> Result<(), String> {
+    let mut errors = 0;
+    for diagnostic in diagnostics {
+        let diagnostic = diagnostic.clone().with_file(source.name.clone());
+        eprintln!("{}", diagnostic.render(&source.text));
+        if diagnostic.is_error() {
+            errors += 1;
         }
-        Err(e) => eprintln!("Failed to read file: {}", e),
     }
+    match errors {
+        0 => Ok(()),
+        1 => Err(format!("could not compile {} due to 1 previous error", source.name)),
+        n => Err(format!(
+            "could not compile {} due to {} previous errors",
+            source.name, n
+        )),
+    }
+}
+
+fn lex(source: &Source) -> Result<Vec<token::Token>, String> {
+    let mut lexer = token::Lexer::new();
+    lexer.tokenize(&source.text);
+    report(source, lexer.diagnostics())?;
+    Ok(lexer.return_tok())
+}
+
+fn parse_tokens(source: &Source, tokens: Vec<token::Token>) -> Result<ast::ASTNode, String> {
+    let mut parser = ast::Parser::new(tokens);
+    let (asts, diagnostics) = parser.parse();
+    report(source, &diagnostics)?;
+    Ok(asts)
+}
+
+fn check(source: &Source, asts: &ast::ASTNode) -> Result<(), String> {
+    let diagnostics = sema::Checker::new().check(asts);
+    report(source, &diagnostics)
+}
+
+fn generate_code(source: &Source, asts: ast::ASTNode) -> Result<codegen::CodeGenerator, String> {
+    let mut code_gen = codegen::CodeGenerator::new();
+    code_gen.set_source_name(&source.name);
+    code_gen.generate_ir(asts);
+    report(source, code_gen.diagnostics())?;
+    Ok(code_gen)
+}
+
+fn compile(options: &Options) -> Result<(), String> {
+    let source = read_source(options)?;
+
+    let tokens = lex(&source)?;
+    if options.emit == Emit::Tokens {
+        let mut dump = String::new();
+        for token in &tokens {
+            dump.push_str(&format!("{:?}\n", token));
+        }
+        return write_output(&options.output(), &dump);
+    }
+
+    let asts = parse_tokens(&source, tokens)?;
+    if options.emit == Emit::Ast {
+        return write_output(&options.output(), &format!("{:#?}\n", asts));
+    }
+
+    check(&source, &asts)?;
+    let code_gen = generate_code(&source, asts)?;
+    match options.output() {
+        Output::Stdout => write_output(&Output::Stdout, code_gen.c_code()),
+        Output::File(path) => code_gen
+            .generate_c_file(&path)
+            .map_err(|e| format!("C source file gen failed with error {}", e)),
+    }
+}
+
+fn write_output(output: &Output, contents: &str) -> Result<(), String> {
+    match output {
+        Output::Stdout => io::stdout()
+            .write_all(contents.as_bytes())
+            .map_err(|e| format!("Failed to write to stdout: {}", e)),
+        Output::File(path) => fs::write(path, contents)
+            .map_err(|e| format!("Failed to write {}: {}", path.display(), e)),
+    }
+}
+
+fn generate(options: &Options) -> Result<codegen::CodeGenerator, String> {
+    let source = read_source(options)?;
+    let tokens = lex(&source)?;
+    let asts = parse_tokens(&source, tokens)?;
+    check(&source, &asts)?;
+    generate_code(&source, asts)
+}
+fn compiler_config(options: &Options) -> CompilerConfig {
+    CompilerConfig {
+        compiler: options.cc.clone(),
+        flags: options.cflags.clone(),
+        keep_temps: options.keep_temps,
+    }
+}
+
+fn build(options: &Options) -> Result<(), String> {
+    let code_gen = generate(options)?;
+    toolchain::build_executable(&code_gen, &options.executable(), &compiler_config(options))
+}
+
+fn run(options: &Options) -> Result<i32, String> {
+    let code_gen = generate(options)?;
+    toolchain::run_program(&code_gen, &compiler_config(options))
+}
 
+fn interpret(options: &Options) -> Result<(), String> {
+    let source = read_source(options)?;
+    let tokens = lex(&source)?;
+    let asts = parse_tokens(&source, tokens)?;
+    check(&source, &asts)?;
+    let result = interp::with_large_stack(|| interp::Interpreter::new().run(&asts));
+    result.map_err(|diagnostic| {
+        let diagnostic = diagnostic.with_file(source.name.clone());
+        eprintln!("{}", diagnostic.render(&source.text));
+        format!("{} stopped with a runtime error", source.name)
+    })
 }
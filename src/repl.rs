@@ -0,0 +1,172 @@
+use crate::ast::{ASTNode, Parser};
+use crate::diagnostic::Diagnostic;
+use crate::interp::Interpreter;
+use crate::sema::Checker;
+use crate::token::{Lexer, Span, Token, TokenType};
+
+use std::io::{self, BufRead, Write};
+
+const PROMPT: &str = "sco> ";
+const CONTINUATION_PROMPT: &str = "...> ";
+const HELP: &str = "\
+Enter statements one at a time, e.g. `VARint x = 3;` or `DISPLAYnumeric x;`.
+IF, WHILE, FOR and FUNC blocks keep prompting until their closing END-... line.
+
+Commands:
+    :tokens    Show the lexer output for the last input
+    :ast       Show the parser output for the last input
+    :vars      List the variables defined so far
+    :help      Print this message
+    :quit      Leave the REPL (Ctrl-D works too)";
+
+/// Read-eval-print loop. Variable state lives in one `Interpreter` and one `Checker`
+/// for the whole session.
+pub struct Repl<'a> {
+    interpreter: Interpreter<'a>,
+    checker: Checker,
+    last_tokens: Vec<Token>,
+    last_ast: Option<ASTNode>,
+}
+
+impl<'a> Repl<'a> {
+    pub fn new() -> Self {
+        Repl {
+            interpreter: Interpreter::new(),
+            checker: Checker::new(),
+            last_tokens: Vec::new(),
+            last_ast: None,
+        }
+    }
+
+    pub fn run(&mut self) -> io::Result<()> {
+        let stdin = io::stdin();
+        let mut pending = String::new();
+
+        println!("Type :help for help, :quit to exit.");
+        loop {
+            let prompt = if pending.is_empty() {
+                PROMPT
+            } else {
+                CONTINUATION_PROMPT
+            };
+            print!("{}", prompt);
+            io::stdout().flush()?;
+
+            //Stdin is only locked per line, since ACCEPT statements read from it too
+            let line = match stdin.lock().lines().next() {
+                Some(line) => line?,
+                None => {
+                    println!();
+                    return Ok(());
+                }
+            };
+
+            if pending.is_empty() {
+                match line.trim() {
+                    "" => continue,
+                    ":quit" | ":q" => return Ok(()),
+                    ":help" => {
+                        println!("{}", HELP);
+                        continue;
+                    }
+                    ":tokens" => {
+                        for token in &self.last_tokens {
+                            println!("{:?}", token);
+                        }
+                        continue;
+                    }
+                    ":ast" => {
+                        match &self.last_ast {
+                            Some(ast) => println!("{:#?}", ast),
+                            None => println!("No AST for the last input."),
+                        }
+                        continue;
+                    }
+                    ":vars" => {
+                        self.print_variables();
+                        continue;
+                    }
+                    cmd if cmd.starts_with(':') => {
+                        println!("Unknown command '{}'. Type :help for help.", cmd);
+                        continue;
+                    }
+                    _ => {}
+                }
+            }
+
+            pending.push_str(&line);
+            pending.push('\n');
+
+            let mut lexer = Lexer::new();
+            lexer.tokenize(&pending);
+            let mut tokens = lexer.return_tok();
+            if open_blocks(&tokens) > 0 {
+                continue;
+            }
+
+            let end = tokens.last().map_or(Span::default(), |t| t.span.after());
+            tokens.push(Token::newtok(TokenType::EOF, String::new(), None, end, None));
+            self.eval(tokens, lexer.diagnostics(), &pending);
+            pending.clear();
+        }
+    }
+
+    fn eval(&mut self, tokens: Vec<Token>, lex_diagnostics: &[Diagnostic], input: &str) {
+        self.last_tokens = tokens.clone();
+        self.last_ast = None;
+        for diagnostic in lex_diagnostics {
+            print_diagnostic(diagnostic, input);
+        }
+        if lex_diagnostics.iter().any(Diagnostic::is_error) {
+            return;
+        }
+
+        let mut parser = Parser::new(tokens);
+        let (ast, parse_diagnostics) = parser.parse();
+        self.last_ast = Some(ast.clone());
+        for diagnostic in &parse_diagnostics {
+            print_diagnostic(diagnostic, input);
+        }
+        if parse_diagnostics.iter().any(Diagnostic::is_error) {
+            return;
+        }
+
+        //Only keep the declarations of inputs that pass the checks and run to the end
+        let mut checker = self.checker.clone();
+        let sema_diagnostics = checker.check(&ast);
+        for diagnostic in &sema_diagnostics {
+            print_diagnostic(diagnostic, input);
+        }
+        if sema_diagnostics.iter().any(Diagnostic::is_error) {
+            return;
+        }
+        match self.interpreter.run(&ast) {
+            Ok(()) => self.checker = checker,
+            Err(diagnostic) => print_diagnostic(&diagnostic, input),
+        }
+    }
+
+    fn print_variables(&self) {
+        let mut variables: Vec<_> = self.interpreter.variables().collect();
+        variables.sort_by(|a, b| a.0.cmp(b.0));
+        for (name, value) in variables {
+            println!("{} = {}", name, value);
+        }
+    }
+}
+
+fn print_diagnostic(diagnostic: &Diagnostic, input: &str) {
+    print!("{}", diagnostic.clone().with_file("<repl>").render(input));
+}
+
+/// Number of blocks opened but not yet closed, so the REPL knows to keep reading.
+fn open_blocks(tokens: &[Token]) -> i32 {
+    tokens.iter().fold(0, |depth, token| match token.token_type {
+        TokenType::If | TokenType::While | TokenType::For | TokenType::Func => depth + 1,
+        TokenType::Endifelseblock
+        | TokenType::EndWhile
+        | TokenType::EndFor
+        | TokenType::EndFunc => depth - 1,
+        _ => depth,
+    })
+}
@@ -0,0 +1,83 @@
+use std::collections::HashMap;
+
+/// Symbol table with one map per open block, innermost last. The checker, codegen and
+/// interpreter all use it, so they agree on which declaration a name refers to:
+/// a name resolves to the innermost block that declares it, and disappears with that
+/// block.
+#[derive(Debug, Clone)]
+pub struct ScopeStack<T> {
+    scopes: Vec<HashMap<String, T>>,
+}
+
+impl<T> Default for ScopeStack<T> {
+    fn default() -> Self {
+        ScopeStack {
+            scopes: vec![HashMap::new()],
+        }
+    }
+}
+
+impl<T> ScopeStack<T> {
+    /// A stack holding just the outermost (program or function) scope.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push(&mut self) {
+        self.scopes.push(HashMap::new());
+    }
+
+    pub fn pop(&mut self) {
+        assert!(self.scopes.len() > 1, "cannot pop the outermost scope");
+        self.scopes.pop();
+    }
+
+    /// Declares `name` in the innermost scope, returning what it replaced there.
+    pub fn declare(&mut self, name: &str, value: T) -> Option<T> {
+        self.innermost().insert(name.to_string(), value)
+    }
+
+    pub fn get(&self, name: &str) -> Option<&T> {
+        self.scopes.iter().rev().find_map(|scope| scope.get(name))
+    }
+
+    pub fn get_mut(&mut self, name: &str) -> Option<&mut T> {
+        self.scopes
+            .iter_mut()
+            .rev()
+            .find_map(|scope| scope.get_mut(name))
+    }
+
+    /// Looks only at the innermost scope, i.e. the block currently being declared into.
+    pub fn get_local(&self, name: &str) -> Option<&T> {
+        self.scopes.last().and_then(|scope| scope.get(name))
+    }
+
+    /// Every visible binding; a shadowed name is listed once, with its innermost value.
+    pub fn iter(&self) -> impl Iterator<Item = (&String, &T)> {
+        self.scopes
+            .iter()
+            .enumerate()
+            .flat_map(|(depth, scope)| scope.iter().map(move |entry| (depth, entry)))
+            .filter(|(depth, (name, _))| {
+                !self.scopes[depth + 1..]
+                    .iter()
+                    .any(|inner| inner.contains_key(*name))
+            })
+            .map(|(_, entry)| entry)
+    }
+
+    /// Bindings of the innermost scope only.
+    pub fn locals(&self) -> impl Iterator<Item = (&String, &T)> {
+        self.scopes.last().into_iter().flatten()
+    }
+
+    /// Every binding in every scope, shadowed ones included.
+    pub fn all(&self) -> impl Iterator<Item = (&String, &T)> {
+        self.scopes.iter().flatten()
+    }
+
+    fn innermost(&mut self) -> &mut HashMap<String, T> {
+        self.scopes.last_mut().expect("scope stack is never empty")
+    }
+}
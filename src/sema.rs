@@ -0,0 +1,594 @@
+use crate::ast::ASTNode;
+use crate::builtins;
+use crate::diagnostic::Diagnostic;
+use crate::scope::ScopeStack;
+use crate::token::{Span, Type};
+
+use std::collections::{HashMap, HashSet};
+
+#[derive(Debug, Clone)]
+struct Symbol {
+    var_type: Type,
+    span: Span,
+}
+
+#[derive(Debug, Clone)]
+struct Function {
+    params: Vec<Type>,
+    return_type: Option<Type>,
+    span: Span,
+}
+
+/// Semantic checks that run on the AST before codegen or the interpreter see it:
+/// every variable is declared once before use and every operand has the right type.
+/// Each IF branch and loop body is its own scope; a FUNC body sees only its parameters.
+#[derive(Debug, Clone, Default)]
+pub struct Checker {
+    symbols: ScopeStack<Symbol>,
+    undeclared: HashSet<String>,
+    functions: HashMap<String, Function>,
+    //Return type of the function being checked; `None` outside any function
+    returns: Option<Option<Type>>,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl Checker {
+    pub fn new() -> Self {
+        Checker::default()
+    }
+
+    /// Checks a whole program. Declarations are remembered across calls, so the REPL
+    /// can feed one input at a time into the same checker.
+    pub fn check(&mut self, program: &ASTNode) -> Vec<Diagnostic> {
+        self.statement(program);
+        std::mem::take(&mut self.diagnostics)
+    }
+
+    fn statement(&mut self, node: &ASTNode) {
+        match node {
+            ASTNode::Program(statements) => {
+                //Declare every function first so calls may come before the definition
+                for statement in statements {
+                    self.declare_function(statement);
+                }
+                self.statements(statements)
+            }
+            ASTNode::VariableDeclaration {
+                identifier,
+                initializer,
+                span,
+                var_type,
+            } => {
+                let var_type = var_type.clone().unwrap_or(Type::Unknown);
+                if let Some(init) = initializer {
+                    let value_type = self.expression(init);
+                    self.expect_assignable(identifier, &var_type, &value_type, *span);
+                }
+                self.declare(identifier, var_type, *span);
+            }
+            ASTNode::ExpressionStatement {
+                expression,
+                identifier,
+                span,
+            } => {
+                let value_type = self.expression(expression);
+                let var_type = self.lookup(identifier, *span);
+                self.expect_assignable(identifier, &var_type, &value_type, *span);
+            }
+            ASTNode::DisplayStatement(identifier, span)
+            | ASTNode::DisplayIntStatement(identifier, span) => {
+                self.expect_display(identifier, &[Type::Int, Type::Float], "a number", *span);
+            }
+            ASTNode::DisplayStringStatement(..) => {}
+            ASTNode::DisplayStringVariable(identifier, span) => {
+                self.expect_display(identifier, &[Type::Str], "a string", *span);
+            }
+            ASTNode::DisplayBoolStatement(identifier, span) => {
+                self.expect_display(identifier, &[Type::Bool], "a boolean", *span);
+            }
+            ASTNode::AcceptIntStatement(identifier, span) => {
+                self.expect_accept(identifier, &[Type::Int, Type::Float], "a number", *span);
+            }
+            ASTNode::AcceptStringStatement(identifier, span) => {
+                self.expect_accept(identifier, &[Type::Str], "a string", *span);
+            }
+            ASTNode::IfStatement {
+                condition,
+                then_block,
+                else_if_blocks,
+                else_block,
+                ..
+            } => {
+                self.condition(condition);
+                self.block(then_block);
+                for (elif_condition, elif_block) in else_if_blocks {
+                    self.condition(elif_condition);
+                    self.block(elif_block);
+                }
+                if let Some(else_statements) = else_block {
+                    self.block(else_statements);
+                }
+            }
+            ASTNode::WhileStatement {
+                condition, body, ..
+            } => {
+                self.condition(condition);
+                self.block(body);
+            }
+            ASTNode::ForStatement {
+                variable,
+                start,
+                end,
+                step,
+                body,
+                span,
+            } => {
+                for bound in [Some(start), Some(end), step.as_ref()].into_iter().flatten() {
+                    if !matches!(self.expression(bound), Type::Int | Type::Unknown) {
+                        self.diagnostics.push(Diagnostic::error(
+                            "E0206",
+                            "FOR bounds and step must be integers",
+                            bound.span().unwrap_or_default(),
+                        ));
+                    }
+                }
+                //The loop variable is an Int that only exists inside the loop
+                self.symbols.push();
+                self.declare(variable, Type::Int, *span);
+                self.block(body);
+                self.symbols.pop();
+            }
+            ASTNode::FunctionDeclaration {
+                params,
+                return_type,
+                body,
+                ..
+            } => {
+                //A function body sees only its parameters, like the C function it becomes
+                let outer_symbols = std::mem::take(&mut self.symbols);
+                let outer_undeclared = std::mem::take(&mut self.undeclared);
+                let outer_returns = self.returns.replace(return_type.clone());
+                for param in params {
+                    self.declare(&param.name, param.var_type.clone(), param.span);
+                }
+                //Parameters and the body's own declarations share one scope, as in C
+                self.statements(body);
+                self.symbols = outer_symbols;
+                self.undeclared = outer_undeclared;
+                self.returns = outer_returns;
+            }
+            ASTNode::ReturnStatement { value, span } => {
+                let value_type = value.as_ref().map(|value| self.expression(value));
+                match (&self.returns, value_type) {
+                    (None, _) => self.diagnostics.push(Diagnostic::error(
+                        "E0208",
+                        "RETURN is only allowed inside a FUNC",
+                        *span,
+                    )),
+                    (Some(None), Some(_)) => self.diagnostics.push(Diagnostic::error(
+                        "E0209",
+                        "This function has no RETURNS type, so RETURN cannot give a value",
+                        *span,
+                    )),
+                    (Some(Some(expected)), None) => {
+                        let message = format!("RETURN needs a value of type {}", expected);
+                        self.diagnostics
+                            .push(Diagnostic::error("E0209", message, *span));
+                    }
+                    (Some(Some(expected)), Some(actual)) => {
+                        if !widens_to(&actual, expected) {
+                            let message =
+                                format!("Cannot return {} from a function returning {}", actual, expected);
+                            self.diagnostics
+                                .push(Diagnostic::error("E0209", message, *span));
+                        }
+                    }
+                    (Some(None), None) => {}
+                }
+            }
+            ASTNode::CallStatement { call, .. } => {
+                if let ASTNode::Call { name, args, span } = call.as_ref() {
+                    self.call(name, args, *span);
+                }
+            }
+            ASTNode::BinaryOp { .. }
+            | ASTNode::UnaryOp { .. }
+            | ASTNode::Identifier(..)
+            | ASTNode::Number(..)
+            | ASTNode::Float(..)
+            | ASTNode::StringLiteral(..)
+            | ASTNode::Boolean(..)
+            | ASTNode::Call { .. } => {
+                self.expression(node);
+            }
+        }
+    }
+
+    fn block(&mut self, statements: &[ASTNode]) {
+        self.symbols.push();
+        self.statements(statements);
+        self.symbols.pop();
+    }
+
+    fn statements(&mut self, statements: &[ASTNode]) {
+        for statement in statements {
+            self.statement(statement);
+        }
+    }
+
+    /// Type of an expression. Problems are reported once and yield `Type::Unknown`,
+    /// which every later check accepts so one mistake doesn't cascade.
+    fn expression(&mut self, node: &ASTNode) -> Type {
+        match node {
+            ASTNode::Number(digits, span) => {
+                if digits.parse::<i64>().is_err() {
+                    self.diagnostics.push(Diagnostic::error(
+                        "E0213",
+                        format!("Integer literal '{}' does not fit in 64 bits", digits),
+                        *span,
+                    ));
+                }
+                Type::Int
+            }
+            ASTNode::Float(digits, span) => {
+                if digits.parse::<f64>().map_or(true, f64::is_infinite) {
+                    self.diagnostics.push(Diagnostic::error(
+                        "E0213",
+                        format!("Float literal '{}' is out of range", digits),
+                        *span,
+                    ));
+                }
+                Type::Float
+            }
+            ASTNode::UnaryOp { .. } if node.negated_literal().is_some() => Type::Int,
+            ASTNode::StringLiteral(..) => Type::Str,
+            ASTNode::Boolean(..) => Type::Bool,
+            ASTNode::Identifier(name, span) => self.lookup(name, *span),
+            ASTNode::Call { name, args, span } => match self.call(name, args, *span) {
+                Some(Some(return_type)) => return_type,
+                Some(None) => {
+                    self.diagnostics.push(Diagnostic::error(
+                        "E0212",
+                        format!("Function '{}' does not return a value", name),
+                        *span,
+                    ));
+                    Type::Unknown
+                }
+                None => Type::Unknown,
+            },
+            ASTNode::BinaryOp {
+                operator,
+                left,
+                right,
+                span,
+            } => {
+                let left = self.expression(left);
+                let right = self.expression(right);
+                self.binary_type(operator, &left, &right, *span)
+            }
+            ASTNode::UnaryOp {
+                operator,
+                operand,
+                span,
+            } => {
+                let operand = self.expression(operand);
+                let (valid, result) = unary_type(operator, &operand);
+                if !valid {
+                    self.diagnostics.push(Diagnostic::error(
+                        "E0203",
+                        format!("Operator '{}' is not supported on {}", operator, operand),
+                        *span,
+                    ));
+                }
+                result
+            }
+            _ => Type::Unknown,
+        }
+    }
+
+    fn binary_type(&mut self, operator: &str, left: &Type, right: &Type, span: Span) -> Type {
+        let (valid, result) = binary_type(operator, left, right);
+        if !valid {
+            self.diagnostics.push(Diagnostic::error(
+                "E0203",
+                format!(
+                    "Operator '{}' is not supported between {} and {}",
+                    operator, left, right
+                ),
+                span,
+            ));
+        }
+        result
+    }
+
+    fn expect_display(&mut self, name: &str, expected: &[Type], shown_as: &str, span: Span) {
+        let actual = self.lookup(name, span);
+        if !expected.contains(&actual) && actual != Type::Unknown {
+            self.diagnostics.push(Diagnostic::error(
+                "E0204",
+                format!(
+                    "Cannot display {} variable '{}' as {}",
+                    actual.to_string().to_lowercase(),
+                    name,
+                    shown_as
+                ),
+                span,
+            ));
+        }
+    }
+
+    fn expect_accept(&mut self, name: &str, expected: &[Type], read_as: &str, span: Span) {
+        let actual = self.lookup(name, span);
+        if !expected.contains(&actual) && actual != Type::Unknown {
+            self.diagnostics.push(Diagnostic::error(
+                "E0214",
+                format!(
+                    "Cannot read {} into {} variable '{}'",
+                    read_as,
+                    actual.to_string().to_lowercase(),
+                    name
+                ),
+                span,
+            ));
+        }
+    }
+
+    /// Adds a variable to the current scope. Redeclaring in the same scope is an error;
+    /// hiding a variable of an enclosing scope only gets a warning.
+    fn declare(&mut self, name: &str, var_type: Type, span: Span) {
+        if let Some(previous) = self.symbols.get_local(name) {
+            self.diagnostics.push(Diagnostic::error(
+                "E0201",
+                format!(
+                    "Variable '{}' is already declared on line {}",
+                    name, previous.span.line
+                ),
+                span,
+            ));
+            return;
+        }
+        if let Some(outer) = self.symbols.get(name) {
+            self.diagnostics.push(Diagnostic::warning(
+                "W0002",
+                format!(
+                    "Variable '{}' shadows the one declared on line {}",
+                    name, outer.span.line
+                ),
+                span,
+            ));
+        }
+        self.symbols.declare(name, Symbol { var_type, span });
+    }
+
+    fn declare_function(&mut self, node: &ASTNode) {
+        let ASTNode::FunctionDeclaration {
+            name,
+            params,
+            return_type,
+            span,
+            ..
+        } = node
+        else {
+            return;
+        };
+        if builtins::lookup(name).is_some() {
+            self.diagnostics.push(Diagnostic::error(
+                "E0207",
+                format!("Function '{}' is already a built-in function", name),
+                *span,
+            ));
+            return;
+        }
+        if let Some(previous) = self.functions.get(name) {
+            self.diagnostics.push(Diagnostic::error(
+                "E0207",
+                format!(
+                    "Function '{}' is already declared on line {}",
+                    name, previous.span.line
+                ),
+                *span,
+            ));
+            return;
+        }
+        self.functions.insert(
+            name.clone(),
+            Function {
+                params: params.iter().map(|p| p.var_type.clone()).collect(),
+                return_type: return_type.clone(),
+                span: *span,
+            },
+        );
+    }
+
+    /// Checks a call's arguments. Gives the function's return type (`Some(None)` when it
+    /// has none), or `None` if the call itself is broken.
+    fn call(&mut self, name: &str, args: &[ASTNode], span: Span) -> Option<Option<Type>> {
+        let arg_types: Vec<Type> = args.iter().map(|arg| self.expression(arg)).collect();
+        let builtin = builtins::lookup(name).map(|builtin| Function {
+            params: builtin.params.to_vec(),
+            return_type: Some(builtin.returns.clone()),
+            span,
+        });
+        let Some(function) = builtin.or_else(|| self.functions.get(name).cloned()) else {
+            self.diagnostics.push(Diagnostic::error(
+                "E0210",
+                format!("Unknown function '{}'", name),
+                span,
+            ));
+            return None;
+        };
+        if arg_types.len() != function.params.len() {
+            self.diagnostics.push(Diagnostic::error(
+                "E0211",
+                format!(
+                    "Function '{}' takes {} argument(s) but {} were given",
+                    name,
+                    function.params.len(),
+                    arg_types.len()
+                ),
+                span,
+            ));
+            return Some(function.return_type);
+        }
+        for (index, (expected, actual)) in function.params.iter().zip(&arg_types).enumerate() {
+            if !widens_to(actual, expected) {
+                self.diagnostics.push(Diagnostic::error(
+                    "E0211",
+                    format!(
+                        "Argument {} of '{}' must be {}, found {}",
+                        index + 1,
+                        name,
+                        expected,
+                        actual
+                    ),
+                    args[index].span().unwrap_or(span),
+                ));
+            }
+        }
+        Some(function.return_type)
+    }
+
+    fn condition(&mut self, condition: &ASTNode) {
+        if self.expression(condition) == Type::Str {
+            self.diagnostics.push(Diagnostic::error(
+                "E0205",
+                "Condition must be a boolean or a number",
+                condition.span().unwrap_or_default(),
+            ));
+        }
+    }
+
+    fn lookup(&mut self, name: &str, span: Span) -> Type {
+        match self.symbols.get(name) {
+            Some(symbol) => symbol.var_type.clone(),
+            None => {
+                //Report each undeclared name once, at its first use
+                if self.undeclared.insert(name.to_string()) {
+                    self.diagnostics.push(Diagnostic::error(
+                        "E0200",
+                        format!("Undeclared variable '{}'", name),
+                        span,
+                    ));
+                }
+                Type::Unknown
+            }
+        }
+    }
+
+    fn expect_assignable(&mut self, name: &str, expected: &Type, actual: &Type, span: Span) {
+        if !widens_to(actual, expected) {
+            self.diagnostics.push(Diagnostic::error(
+                "E0202",
+                format!(
+                    "Cannot assign {} value to {} variable '{}'",
+                    actual, expected, name
+                ),
+                span,
+            ));
+        }
+    }
+}
+
+/// Result type of a binary operator, and whether the operands are allowed. The result
+/// type is known even when an operand is wrong, so checking carries on as if the
+/// operands had been right. Arithmetic on an Int and a Float promotes the Int, giving a
+/// Float. Codegen uses this too, to pick the C for each operator.
+pub fn binary_type(operator: &str, left: &Type, right: &Type) -> (bool, Type) {
+    let numeric = is_numeric(left) && is_numeric(right);
+    match operator {
+        "+" | "-" | "*" | "/" if *left == Type::Float || *right == Type::Float => {
+            (numeric, Type::Float)
+        }
+        //Strings concatenate and compare with each other, but never mix with numbers
+        "+" if *left == Type::Str || *right == Type::Str => {
+            (is_type(left, Type::Str) && is_type(right, Type::Str), Type::Str)
+        }
+        "+" | "-" | "*" | "/" => (numeric, Type::Int),
+        "%" | "**" => (is_type(left, Type::Int) && is_type(right, Type::Int), Type::Int),
+        "<" | "<=" | ">" | ">=" if *left == Type::Str || *right == Type::Str => {
+            (is_type(left, Type::Str) && is_type(right, Type::Str), Type::Bool)
+        }
+        "<" | "<=" | ">" | ">=" => (numeric, Type::Bool),
+        "==" | "!=" => {
+            let same = left == right || *left == Type::Unknown || *right == Type::Unknown;
+            (same || numeric, Type::Bool)
+        }
+        "AND" | "OR" => (is_type(left, Type::Bool) && is_type(right, Type::Bool), Type::Bool),
+        _ => (false, Type::Unknown),
+    }
+}
+
+/// Result type of `-` or `NOT`, and whether the operand is allowed, like `binary_type`.
+pub fn unary_type(operator: &str, operand: &Type) -> (bool, Type) {
+    match operator {
+        "-" if *operand == Type::Float => (true, Type::Float),
+        "-" => (is_type(operand, Type::Int), Type::Int),
+        _ => (is_type(operand, Type::Bool), Type::Bool),
+    }
+}
+
+/// `Unknown` stands for an operand that was already reported, so it passes every check.
+fn is_type(actual: &Type, wanted: Type) -> bool {
+    *actual == wanted || *actual == Type::Unknown
+}
+
+fn is_numeric(actual: &Type) -> bool {
+    matches!(actual, Type::Int | Type::Float | Type::Unknown)
+}
+
+/// Whether a value of type `actual` may be stored where `expected` is declared. An Int
+/// widens to a Float; a Float never narrows to an Int implicitly.
+fn widens_to(actual: &Type, expected: &Type) -> bool {
+    actual == expected
+        || *actual == Type::Unknown
+        || *expected == Type::Unknown
+        || (*actual == Type::Int && *expected == Type::Float)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ast::Parser;
+    use crate::token::Lexer;
+
+    /// Codes of the diagnostics sema reports for a program that parses cleanly.
+    fn codes(program: &str) -> Vec<&'static str> {
+        let mut lexer = Lexer::new();
+        lexer.tokenize(program);
+        let (ast, diagnostics) = Parser::new(lexer.return_tok()).parse();
+        assert!(diagnostics.is_empty(), "{}: {:?}", program, diagnostics);
+        Checker::new().check(&ast).iter().map(|d| d.code).collect()
+    }
+
+    #[test]
+    fn declarations_assignments_and_display_are_checked() {
+        let program = "VARint x = 1;\nVARint x = 2;\ny = 3;\nVARstr s = 5;\n\
+                       VARint n = x - \"a\";\nDISPLAYstring x;\nEND\n";
+        assert_eq!(
+            codes(program),
+            ["E0201", "E0200", "E0202", "E0203", "E0204"]
+        );
+        assert!(codes("VARint x = 1;\nVARfloat f = x;\nDISPLAY f;\nEND\n").is_empty());
+    }
+
+    #[test]
+    fn blocks_scope_their_declarations() {
+        let program = "VARint x = 1;\nIF (x > 0) THEN\n    VARint x = 2;\n    VARint inner = x;\n\
+                       END-IF\nDISPLAY inner;\nEND\n";
+        assert_eq!(codes(program), ["W0002", "E0200"]);
+    }
+
+    #[test]
+    fn booleans_only_mix_with_booleans() {
+        let program = "VARbool b = TRUE;\nVARint n = b;\nVARint m = b + 1;\n\
+                       VARbool c = NOT 3;\nIF (\"text\") THEN\n    DISPLAYbool b;\nEND-IF\n\
+                       VARbool d = b AND n > 0;\nEND\n";
+        assert_eq!(codes(program), ["E0202", "E0203", "E0203", "E0205"]);
+    }
+
+    #[test]
+    fn only_a_negated_literal_reaches_i64_min() {
+        assert!(codes("VARint m = -9223372036854775808;\nEND\n").is_empty());
+        assert_eq!(codes("VARint m = 9223372036854775808;\nEND\n"), ["E0213"]);
+    }
+}
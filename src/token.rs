@@ -1,13 +1,12 @@
-use std::borrow::Borrow;
 use std::collections::HashMap;
 use std::fmt;
 use std::iter::Peekable;
-use std::ops::DerefMut;
 use std::str::Chars;
-use std::string;
 
 use regex::Regex;
 
+use crate::diagnostic::Diagnostic;
+
 const ANONYMOUS_FUNCTION_NAME: &str = "anonymous";
 
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -15,27 +14,58 @@ pub enum TokenType {
     Identifier,
     IntVar,
     StrVar,
+    BoolVar,
+    FloatVar,
     String,
     Number,
+    Float,
     Plus,
     Minus,
     Slash,
     Star,
+    Percent,
+    StarStar,
+    PlusEqual,
+    MinusEqual,
+    StarEqual,
+    SlashEqual,
+    PercentEqual,
     Semicolon,
     LParen,
     RParen,
+    Comma,
     Equals,
+    EqualEqual,
+    NotEqual,
     Comment,
-    Var,
     Display,
     EOF,
     DisplayStr,
     DisplayInt,
+    DisplayBool,
+    AcceptInt,
+    AcceptStr,
+    True,
+    False,
+    And,
+    Or,
+    Not,
     If,
     Then,
     Else,
     Elif,
     Endifelseblock,
+    While,
+    Do,
+    EndWhile,
+    For,
+    To,
+    Step,
+    EndFor,
+    Func,
+    Returns,
+    Return,
+    EndFunc,
     LessThanOrEqual,
     GreaterThanOrEqual,
     LessThan,
@@ -46,6 +76,8 @@ pub enum TokenType {
 pub enum Type {
     Int,
     Str,
+    Bool,
+    Float,
     Unknown,
 }
 
@@ -54,6 +86,8 @@ impl fmt::Display for Type {
         match self {
             Type::Int => write!(f, "Int"),
             Type::Str => write!(f, "Str"),
+            Type::Bool => write!(f, "Bool"),
+            Type::Float => write!(f, "Float"),
             Type::Unknown => write!(f, "Unknown"),
         }
     }
@@ -68,12 +102,41 @@ pub enum Literal {
     Null,
 }
 
+/// Location of a piece of source text. `line` and `column` are 1-based (column counts
+/// chars), `start`/`end` are byte offsets into the source, end exclusive.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Span {
+    pub line: u32,
+    pub column: u32,
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Span {
+    /// Span covering both `self` and `other`, starting where `self` starts.
+    pub fn to(self, other: Span) -> Span {
+        Span {
+            end: other.end.max(self.end),
+            ..self
+        }
+    }
+
+    /// Empty span right after `self`, used to point at something that is missing.
+    pub fn after(self) -> Span {
+        Span {
+            column: self.column + (self.end - self.start) as u32,
+            start: self.end,
+            ..self
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct Token {
     pub token_type: TokenType,
     pub lexeme: String,
     pub literal: Option<Literal>,
-    pub line: u32,
+    pub span: Span,
     pub var_type: Option<Type>,
 }
 
@@ -82,14 +145,14 @@ impl Token {
         token_type: TokenType,
         lexeme: String,
         literal: Option<Literal>,
-        line: u32,
+        span: Span,
         var_type: Option<Type>,
     ) -> Self {
         Token {
             token_type,
             lexeme,
             literal,
-            line,
+            span,
             var_type,
         }
     }
@@ -99,8 +162,11 @@ pub struct Lexer<'a> {
     source: Option<Peekable<Chars<'a>>>,
     tokens: Vec<Token>,
     line: u32,
+    column: u32,
+    offset: usize,
     keywords: HashMap<String, TokenType>,
     types: HashMap<String, Type>,
+    diagnostics: Vec<Diagnostic>,
 }
 
 impl<'a> Lexer<'a> {
@@ -109,8 +175,11 @@ impl<'a> Lexer<'a> {
             source: None,
             tokens: Vec::new(),
             line: 1, //Counts lines
+            column: 1,
+            offset: 0,
             keywords: HashMap::new(), //keeps track of lang rerseved words
             types: HashMap::new(),
+            diagnostics: Vec::new(),
         };
         lexer
             .keywords
@@ -118,6 +187,12 @@ impl<'a> Lexer<'a> {
         lexer
             .keywords
             .insert("VARstr".to_string(), TokenType::StrVar);
+        lexer
+            .keywords
+            .insert("VARbool".to_string(), TokenType::BoolVar);
+        lexer
+            .keywords
+            .insert("VARfloat".to_string(), TokenType::FloatVar);
         lexer
             .keywords
             .insert("DISPLAY".to_string(), TokenType::Display);
@@ -127,13 +202,56 @@ impl<'a> Lexer<'a> {
         lexer
             .keywords
             .insert("DISPLAYstring".to_string(), TokenType::DisplayStr);
+        lexer
+            .keywords
+            .insert("DISPLAYbool".to_string(), TokenType::DisplayBool);
+        lexer
+            .keywords
+            .insert("ACCEPTnumeric".to_string(), TokenType::AcceptInt);
+        lexer
+            .keywords
+            .insert("ACCEPTstring".to_string(), TokenType::AcceptStr);
+        lexer.keywords.insert("TRUE".to_string(), TokenType::True);
+        lexer.keywords.insert("FALSE".to_string(), TokenType::False);
+        lexer.keywords.insert("AND".to_string(), TokenType::And);
+        lexer.keywords.insert("OR".to_string(), TokenType::Or);
+        lexer.keywords.insert("NOT".to_string(), TokenType::Not);
         lexer.keywords.insert("END".to_string(), TokenType::EOF);
         lexer.keywords.insert("IF".to_string(), TokenType::If);
         lexer.keywords.insert("THEN".to_string(), TokenType::Then);
         lexer.keywords.insert("ELSE".to_string(), TokenType::Else);
+        lexer
+            .keywords
+            .insert("ELSE-IF".to_string(), TokenType::Elif);
+        lexer.keywords.insert("ELIF".to_string(), TokenType::Elif);
         lexer
             .keywords
             .insert("END-IF".to_string(), TokenType::Endifelseblock);
+        lexer.keywords.insert("WHILE".to_string(), TokenType::While);
+        lexer.keywords.insert("DO".to_string(), TokenType::Do);
+        lexer
+            .keywords
+            .insert("END-WHILE".to_string(), TokenType::EndWhile);
+        lexer.keywords.insert("FOR".to_string(), TokenType::For);
+        lexer.keywords.insert("TO".to_string(), TokenType::To);
+        lexer.keywords.insert("STEP".to_string(), TokenType::Step);
+        lexer
+            .keywords
+            .insert("END-FOR".to_string(), TokenType::EndFor);
+        lexer.keywords.insert("FUNC".to_string(), TokenType::Func);
+        lexer
+            .keywords
+            .insert("RETURNS".to_string(), TokenType::Returns);
+        lexer.keywords.insert("RETURN".to_string(), TokenType::Return);
+        lexer
+            .keywords
+            .insert("END-FUNC".to_string(), TokenType::EndFunc);
+
+        //Type names, used after RETURNS
+        lexer.types.insert("int".to_string(), Type::Int);
+        lexer.types.insert("str".to_string(), Type::Str);
+        lexer.types.insert("bool".to_string(), Type::Bool);
+        lexer.types.insert("float".to_string(), Type::Float);
 
         lexer
     }
@@ -141,47 +259,116 @@ impl<'a> Lexer<'a> {
     pub fn tokenize(&mut self, input: &'a str) {
         self.source = Some(input.chars().peekable());
         let mut buffer = String::new();
+        let mut buffer_start = self.position();
 
-        while let Some(ch) = self.source.as_mut().unwrap().next() {
+        loop {
+            let start = self.position();
+            let Some(ch) = self.next_char() else {
+                break;
+            };
             match ch {
-                '+' | '*' | '/' | '=' | '(' | ')' | ';' => {
-                    self.handle_buffer(&mut buffer);
+                //The point and exponent sign of 1.5 and 2e-3 stay in the number
+                '.' if Self::in_number(&buffer)
+                    && self.peek_char().is_some_and(|c| c.is_ascii_digit()) =>
+                {
+                    buffer.push(ch);
+                }
+                '+' | '-' if Self::in_number(&buffer)
+                    && buffer.ends_with(['e', 'E'])
+                    && self.peek_char().is_some_and(|c| c.is_ascii_digit()) =>
+                {
+                    buffer.push(ch);
+                }
+                '/' if matches!(self.peek_char(), Some('/' | '*')) => {
+                    self.handle_buffer(&mut buffer, buffer_start);
+                    self.comment(start);
+                }
+                '=' | '!' if self.peek_char() == Some('=') => {
+                    self.handle_buffer(&mut buffer, buffer_start);
+                    self.next_char();
+                    let token_type = if ch == '=' {
+                        TokenType::EqualEqual
+                    } else {
+                        TokenType::NotEqual
+                    };
+                    self.tokens.push(Token::newtok(
+                        token_type,
+                        format!("{}=", ch),
+                        None,
+                        self.span_from(start),
+                        None,
+                    ));
+                }
+                '+' | '-' | '*' | '/' | '%' if self.peek_char() == Some('=') => {
+                    self.handle_buffer(&mut buffer, buffer_start);
+                    self.next_char();
+                    self.tokens.push(Token::newtok(
+                        match ch {
+                            '+' => TokenType::PlusEqual,
+                            '-' => TokenType::MinusEqual,
+                            '*' => TokenType::StarEqual,
+                            '/' => TokenType::SlashEqual,
+                            '%' => TokenType::PercentEqual,
+                            _ => unreachable!(),
+                        },
+                        format!("{}=", ch),
+                        None,
+                        self.span_from(start),
+                        None,
+                    ));
+                }
+                '*' if self.peek_char() == Some('*') => {
+                    self.handle_buffer(&mut buffer, buffer_start);
+                    self.next_char();
+                    self.tokens.push(Token::newtok(
+                        TokenType::StarStar,
+                        "**".to_string(),
+                        None,
+                        self.span_from(start),
+                        None,
+                    ));
+                }
+                '+' | '*' | '/' | '%' | '=' | '(' | ')' | ',' | ';' => {
+                    self.handle_buffer(&mut buffer, buffer_start);
                     self.tokens.push(Token::newtok(
                         match ch {
                             '+' => TokenType::Plus,
 
                             '*' => TokenType::Star,
                             '/' => TokenType::Slash,
+                            '%' => TokenType::Percent,
                             '=' => TokenType::Equals,
                             '(' => TokenType::LParen,
                             ')' => TokenType::RParen,
+                            ',' => TokenType::Comma,
                             ';' => TokenType::Semicolon,
                             _ => unreachable!(),
                         },
                         ch.to_string(),
                         None,
-                        self.line,
+                        self.span_from(start),
                         None,
                     ));
-                    if ch == ';' {
-                        self.line += 1;
-                    }
                 }
-                '"' => self.string_handle(),
+                '"' => {
+                    self.handle_buffer(&mut buffer, buffer_start);
+                    self.string_handle(start);
+                }
                 ch if ch.is_whitespace() => {
-                    self.handle_buffer(&mut buffer);
+                    self.handle_buffer(&mut buffer, buffer_start);
                 }
 
                 '-' =>  {
-                    if self.source.as_mut().unwrap().peek() == Some(&'I') && buffer == "END" {
+                    //END-IF, END-WHILE, ..., and ELSE-IF are single keywords
+                    if (buffer == "END" || buffer == "ELSE") && self.peek_char().is_some_and(|c| c.is_alphabetic()) {
                         buffer.push(ch);
                     } else {
-                        self.handle_buffer(&mut buffer);
+                        self.handle_buffer(&mut buffer, buffer_start);
                         self.tokens.push(Token::newtok(
                             TokenType::Minus,
                             ch.to_string(),
                             None,
-                            self.line,
+                            self.span_from(start),
                             None,
                         ));
                     }
@@ -189,26 +376,26 @@ impl<'a> Lexer<'a> {
 
 
                 '<' | '>' => {
-                    self.handle_buffer(&mut buffer);
-                    let next_char = self.source.as_mut().unwrap().peek();
+                    self.handle_buffer(&mut buffer, buffer_start);
+                    let next_char = self.peek_char();
                     match (ch, next_char) {
-                        ('<', Some(&'=')) => {
-                            self.source.as_mut().unwrap().next();
+                        ('<', Some('=')) => {
+                            self.next_char();
                             self.tokens.push(Token::newtok(
                                 TokenType::LessThanOrEqual,
                                 "<=".to_string(),
                                 None,
-                                self.line,
+                                self.span_from(start),
                                 None,
                             ));
                         }
-                        ('>', Some(&'=')) => {
-                            self.source.as_mut().unwrap().next();
+                        ('>', Some('=')) => {
+                            self.next_char();
                             self.tokens.push(Token::newtok(
                                 TokenType::GreaterThanOrEqual,
                                 ">=".to_string(),
                                 None,
-                                self.line,
+                                self.span_from(start),
                                 None,
                             ));
                         }
@@ -217,7 +404,7 @@ impl<'a> Lexer<'a> {
                                 TokenType::LessThan,
                                 "<".to_string(),
                                 None,
-                                self.line,
+                                self.span_from(start),
                                 None,
                             ));
                         }
@@ -226,7 +413,7 @@ impl<'a> Lexer<'a> {
                                 TokenType::GreaterThan,
                                 ">".to_string(),
                                 None,
-                                self.line,
+                                self.span_from(start),
                                 None,
                             ));
                         }
@@ -236,34 +423,190 @@ impl<'a> Lexer<'a> {
 
 
                 
-                ch if ch.is_alphanumeric() || ch == '_' => buffer.push(ch),
+                ch if ch.is_alphanumeric() || ch == '_' => {
+                    if buffer.is_empty() {
+                        buffer_start = start;
+                    }
+                    buffer.push(ch)
+                }
                 _ => {
-                    self.handle_buffer(&mut buffer);
+                    self.handle_buffer(&mut buffer, buffer_start);
+                    self.diagnostics.push(Diagnostic::error(
+                        "E0001",
+                        format!("Unexpected character '{}'", ch),
+                        self.span_from(start),
+                    ));
+                }
+            }
+        }
+        self.handle_buffer(&mut buffer, buffer_start);
+
+        if let Some(end) = self.tokens.iter().position(|t| t.token_type == TokenType::EOF) {
+            let mut after_end = self.tokens[end + 1..].iter();
+            if let Some(extra) = after_end.find(|t| t.token_type != TokenType::Comment) {
+                self.diagnostics.push(Diagnostic::warning(
+                    "W0001",
+                    "Code after END is ignored",
+                    extra.span,
+                ));
+            }
+        }
+    }
+
+    /// Reads a `//` comment up to the end of its line, or a `/* */` comment up to the
+    /// first `*/` (they don't nest). Comments stay in the token stream as trivia; the
+    /// parser drops them.
+    fn comment(&mut self, start: Span) {
+        let mut text = String::from('/');
+        let block = self.next_char() == Some('*');
+        text.push(if block { '*' } else { '/' });
+        if block {
+            loop {
+                match self.next_char() {
+                    Some('*') if self.peek_char() == Some('/') => {
+                        self.next_char();
+                        text.push_str("*/");
+                        break;
+                    }
+                    Some(ch) => text.push(ch),
+                    None => {
+                        //Point at the opening /*, like an unterminated string
+                        self.diagnostics.push(Diagnostic::error(
+                            "E0004",
+                            "Unterminated block comment",
+                            Span {
+                                end: start.start + 2,
+                                ..start
+                            },
+                        ));
+                        return;
+                    }
                 }
             }
+        } else {
+            while let Some(ch) = self.peek_char().filter(|&ch| ch != '\n') {
+                text.push(ch);
+                self.next_char();
+            }
+        }
+        self.tokens.push(Token::newtok(
+            TokenType::Comment,
+            text,
+            None,
+            self.span_from(start),
+            None,
+        ));
+    }
+
+    /// Decodes the escape after a `\\` in a string: `\\n`, `\\t`, `\\\\`, `\\"` or
+    /// `\\u{hex}`. Anything else is reported and dropped from the string.
+    fn escape(&mut self, start: Span) -> Option<char> {
+        let kind = self.next_char()?;
+        let mut text = format!("\\{}", kind);
+        let decoded = match kind {
+            'n' => Some('\n'),
+            't' => Some('\t'),
+            '\\' => Some('\\'),
+            '"' => Some('"'),
+            'u' if self.peek_char() == Some('{') => {
+                self.next_char();
+                let mut hex = String::new();
+                while let Some(ch) = self.peek_char().filter(|ch| ch.is_ascii_hexdigit()) {
+                    hex.push(ch);
+                    self.next_char();
+                }
+                text.push('{');
+                text.push_str(&hex);
+                let closed = self.peek_char() == Some('}');
+                if closed {
+                    self.next_char();
+                    text.push('}');
+                }
+                u32::from_str_radix(&hex, 16)
+                    .ok()
+                    .filter(|_| closed && hex.len() <= 6)
+                    .and_then(char::from_u32)
+            }
+            _ => None,
+        };
+        if decoded.is_none() {
+            self.diagnostics.push(Diagnostic::error(
+                "E0005",
+                format!("Invalid escape sequence '{}'", text),
+                self.span_from(start),
+            ));
+        }
+        decoded
+    }
+
+    fn in_number(buffer: &str) -> bool {
+        buffer.starts_with(|c: char| c.is_ascii_digit())
+    }
+
+    fn next_char(&mut self) -> Option<char> {
+        //Consumes one char and moves the line/column/offset cursor past it
+        let ch = self.source.as_mut()?.next()?;
+        self.offset += ch.len_utf8();
+        if ch == '\n' {
+            self.line += 1;
+            self.column = 1;
+        } else {
+            self.column += 1;
+        }
+        Some(ch)
+    }
+
+    fn peek_char(&mut self) -> Option<char> {
+        self.source.as_mut()?.peek().copied()
+    }
+
+    fn position(&self) -> Span {
+        Span {
+            line: self.line,
+            column: self.column,
+            start: self.offset,
+            end: self.offset,
+        }
+    }
+
+    fn span_from(&self, start: Span) -> Span {
+        Span {
+            end: self.offset,
+            ..start
         }
-        self.handle_buffer(&mut buffer);
     }
 
-    fn handle_buffer(&mut self, buffer: &mut String) {
+    fn handle_buffer(&mut self, buffer: &mut String, start: Span) {
         if !buffer.is_empty() {
             let re_integer = Regex::new(r"^\d+$").unwrap();
+            let re_float = Regex::new(r"^\d+(\.\d+)?([eE][+-]?\d+)?$").unwrap();
             let re_identifier = Regex::new(r"^[a-zA-Z_][a-zA-Z0-9_]*$").unwrap();
+            //The buffer is always one contiguous run of source text
+            let span = Span {
+                end: start.start + buffer.len(),
+                ..start
+            };
 
             match buffer.as_str() {
                 buffer if self.keywords.contains_key(buffer) => {
                     let token_type = self.keywords.get(buffer).unwrap().clone();
-                    let literal = Some(Literal::String(buffer.to_string()));
+                    let literal = match token_type {
+                        TokenType::True => Some(Literal::Boolean(true)),
+                        TokenType::False => Some(Literal::Boolean(false)),
+                        _ => Some(Literal::String(buffer.to_string())),
+                    };
                     let var_type = match token_type {
                         TokenType::IntVar => Some(Type::Int),
+                        TokenType::FloatVar => Some(Type::Float),
                         TokenType::StrVar => Some(Type::Str),
+                        TokenType::BoolVar | TokenType::True | TokenType::False => Some(Type::Bool),
                         _ => None,
                     };
                     self.tokens.push(Token::newtok(
                         token_type,
                         buffer.to_string(),
                         literal,
-                        self.line,
+                        span,
                         var_type,
                     ));
                 }
@@ -273,17 +616,27 @@ impl<'a> Lexer<'a> {
                         TokenType::Identifier,
                         buffer.to_string(),
                         Some(Literal::String(buffer.to_string())),
-                        self.line,
+                        span,
                         Some(var_type),
                     ));
                 }
                 _ if re_integer.is_match(buffer) => {
-                    let int_value = buffer.parse::<i64>().unwrap();
+                    //Out-of-range digits are left to the checker: after a minus sign,
+                    //9223372036854775808 is still a valid literal
                     self.tokens.push(Token::newtok(
                         TokenType::Number,
                         buffer.clone(),
-                        Some(Literal::Integer(int_value)),
-                        self.line,
+                        buffer.parse::<i64>().ok().map(Literal::Integer),
+                        span,
+                        None,
+                    ));
+                }
+                _ if re_float.is_match(buffer) => {
+                    self.tokens.push(Token::newtok(
+                        TokenType::Float,
+                        buffer.clone(),
+                        buffer.parse::<f64>().ok().map(Literal::Float),
+                        span,
                         None,
                     ));
                 }
@@ -292,28 +645,43 @@ impl<'a> Lexer<'a> {
                         TokenType::Identifier,
                         buffer.clone(),
                         Some(Literal::String(buffer.clone())),
-                        self.line,
+                        span,
                         None,
                     ));
                 }
                 _ => {
-                    println!("Unexpected character");
+                    self.diagnostics.push(Diagnostic::error(
+                        "E0003",
+                        format!("'{}' is neither a number nor an identifier", buffer),
+                        span,
+                    ));
                 }
             }
             buffer.clear();
         }
     }
 
-    fn string_handle(&mut self) {
+    fn string_handle(&mut self, start: Span) {
         let mut string_content = String::new();
-        while let Some(cha) = self.source.as_mut().unwrap().next() {
+        while let Some(cha) = self.next_char() {
             match cha {
+                '\\' => {
+                    //The backslash is one byte and never a line break
+                    let escape_start = Span {
+                        column: self.column - 1,
+                        start: self.offset - 1,
+                        ..self.position()
+                    };
+                    if let Some(ch) = self.escape(escape_start) {
+                        string_content.push(ch);
+                    }
+                }
                 '"' => {
                     self.tokens.push(Token::newtok(
                         TokenType::String,
                         string_content.clone(),
                         Some(Literal::String(string_content.clone())),
-                        self.line,
+                        self.span_from(start),
                         Some(Type::Str),
                     ));
                     return;
@@ -321,7 +689,15 @@ impl<'a> Lexer<'a> {
                 _ => string_content.push(cha),
             }
         }
-        println!("Error: Unterminated string literal");
+        //Point at the opening quote; the rest of the file is swallowed by the string
+        self.diagnostics.push(Diagnostic::error(
+            "E0002",
+            "Unterminated string literal",
+            Span {
+                end: start.start + 1,
+                ..start
+            },
+        ));
     }
 
     
@@ -332,4 +708,87 @@ impl<'a> Lexer<'a> {
     pub fn return_tok(&mut self) -> Vec<Token> {
         self.tokens.clone()
     }
+
+    pub fn diagnostics(&self) -> &[Diagnostic] {
+        &self.diagnostics
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Type, lexeme and line of every token in `source`, plus the diagnostic codes.
+    fn lex(source: &str) -> (Vec<(TokenType, String, u32)>, Vec<&'static str>) {
+        let mut lexer = Lexer::new();
+        lexer.tokenize(source);
+        let codes = lexer.diagnostics().iter().map(|d| d.code).collect();
+        let tokens = lexer
+            .return_tok()
+            .into_iter()
+            .map(|t| (t.token_type, t.lexeme, t.span.line))
+            .collect();
+        (tokens, codes)
+    }
+
+    #[test]
+    fn line_comments_run_to_the_end_of_the_line() {
+        let (tokens, codes) = lex("x // y\nz");
+        assert!(codes.is_empty(), "{:?}", codes);
+        assert_eq!(tokens[1], (TokenType::Comment, "// y".to_string(), 1));
+        assert_eq!(tokens[2].1, "z");
+        assert_eq!(tokens[2].2, 2);
+    }
+
+    #[test]
+    fn block_comments_keep_line_numbers_counting() {
+        let (tokens, codes) = lex("/* one\ntwo\nthree */\nx");
+        assert!(codes.is_empty(), "{:?}", codes);
+        assert_eq!(tokens[0].0, TokenType::Comment);
+        assert_eq!(tokens[0].1, "/* one\ntwo\nthree */");
+        assert_eq!(tokens[1].1, "x");
+        assert_eq!(tokens[1].2, 4);
+    }
+
+    #[test]
+    fn slash_outside_a_comment_is_division() {
+        let (tokens, codes) = lex("a/2");
+        assert!(codes.is_empty(), "{:?}", codes);
+        let types: Vec<_> = tokens.into_iter().map(|t| t.0).collect();
+        assert!(types.contains(&TokenType::Slash), "{:?}", types);
+        assert!(!types.contains(&TokenType::Comment), "{:?}", types);
+    }
+
+    #[test]
+    fn unterminated_block_comment_is_reported() {
+        let (_, codes) = lex("x /* never closed\n");
+        assert_eq!(codes, ["E0004"]);
+    }
+
+    #[test]
+    fn string_escapes_are_decoded() {
+        let (tokens, codes) = lex(r#""a\n\t\\\"\u{41}\u{1F600}""#);
+        assert!(codes.is_empty(), "{:?}", codes);
+        assert_eq!(tokens[0].0, TokenType::String);
+        assert_eq!(tokens[0].1, "a\n\t\\\"A\u{1F600}");
+    }
+
+    #[test]
+    fn invalid_escapes_are_reported_and_dropped() {
+        for source in [
+            r#""a\qb""#,
+            r#""a\u{}b""#,
+            r#""a\u{110000}b""#,
+            r#""a\u{D800}b""#,
+            r#""a\u{1234567}b""#,
+            r#""a\u{41 b""#,
+            r#""a\u41b""#,
+        ] {
+            let (tokens, codes) = lex(source);
+            assert_eq!(codes, ["E0005"], "{}", source);
+            assert_eq!(tokens[0].0, TokenType::String, "{}", source);
+            assert!(tokens[0].1.starts_with('a'), "{}", source);
+            assert!(tokens[0].1.ends_with('b'), "{}", source);
+        }
+    }
 }
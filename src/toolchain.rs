@@ -0,0 +1,141 @@
+use crate::codegen::CodeGenerator;
+
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, ExitStatus};
+
+const DEFAULT_COMPILERS: [&str; 3] = ["cc", "gcc", "clang"];
+
+pub struct CompilerConfig {
+    pub compiler: Option<String>,
+    pub flags: Vec<String>,
+    pub keep_temps: bool,
+}
+
+/// Scratch directory for generated C, removed again when dropped unless `keep` is set.
+pub struct TempDir {
+    path: PathBuf,
+    keep: bool,
+}
+
+impl TempDir {
+    pub fn new(prefix: &str, keep: bool) -> Result<Self, String> {
+        let path = env::temp_dir().join(format!("{}-{}", prefix, std::process::id()));
+        fs::create_dir_all(&path)
+            .map_err(|e| format!("Failed to create {}: {}", path.display(), e))?;
+        Ok(TempDir { path, keep })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        if self.keep {
+            eprintln!("note: temporary files kept in {}", self.path.display());
+        } else {
+            let _ = fs::remove_dir_all(&self.path);
+        }
+    }
+}
+
+/// Picks the compiler to run: `--cc`, then `$CC`, then the first of cc/gcc/clang on PATH.
+pub fn find_compiler(requested: Option<&str>) -> Result<String, String> {
+    if let Some(cc) = requested {
+        return Ok(cc.to_string());
+    }
+    if let Ok(cc) = env::var("CC") {
+        if !cc.trim().is_empty() {
+            return Ok(cc);
+        }
+    }
+    DEFAULT_COMPILERS
+        .iter()
+        .find(|name| on_path(name))
+        .map(|name| name.to_string())
+        .ok_or_else(|| "No C compiler found (tried cc, gcc, clang); pass one with --cc".to_string())
+}
+
+fn on_path(program: &str) -> bool {
+    let Some(paths) = env::var_os("PATH") else {
+        return false;
+    };
+    env::split_paths(&paths).any(|dir| {
+        let candidate = dir.join(program);
+        candidate.is_file() || candidate.with_extension(env::consts::EXE_EXTENSION).is_file()
+    })
+}
+
+/// Writes the generated C into a scratch directory and compiles it to `exe`.
+pub fn build_executable(
+    code_gen: &CodeGenerator,
+    exe: &Path,
+    config: &CompilerConfig,
+) -> Result<(), String> {
+    let temp = TempDir::new("sco-build", config.keep_temps)?;
+    compile_c(code_gen, temp.path(), exe, config)
+}
+
+/// Compiles the program in a scratch directory, runs it with the terminal's
+/// stdin/stdout/stderr attached, and returns its exit code.
+pub fn run_program(
+    code_gen: &CodeGenerator,
+    config: &CompilerConfig,
+) -> Result<i32, String> {
+    let temp = TempDir::new("sco-run", config.keep_temps)?;
+    let exe = temp.path().join("program").with_extension(env::consts::EXE_EXTENSION);
+    compile_c(code_gen, temp.path(), &exe, config)?;
+
+    let status = Command::new(&exe)
+        .status()
+        .map_err(|e| format!("Failed to run {}: {}", exe.display(), e))?;
+    Ok(exit_code(status))
+}
+
+#[cfg(unix)]
+fn exit_code(status: ExitStatus) -> i32 {
+    use std::os::unix::process::ExitStatusExt;
+    //Follow the shell convention for programs killed by a signal
+    status
+        .code()
+        .unwrap_or_else(|| 128 + status.signal().unwrap_or(0))
+}
+
+#[cfg(not(unix))]
+fn exit_code(status: ExitStatus) -> i32 {
+    status.code().unwrap_or(1)
+}
+
+/// Compiles the generated C in `dir` to `exe`.
+fn compile_c(
+    code_gen: &CodeGenerator,
+    dir: &Path,
+    exe: &Path,
+    config: &CompilerConfig,
+) -> Result<(), String> {
+    let compiler = find_compiler(config.compiler.as_deref())?;
+    let c_path = dir.join("code.c");
+    code_gen
+        .generate_c_file(&c_path)
+        .map_err(|e| format!("C source file gen failed with error {}", e))?;
+
+    let output = Command::new(&compiler)
+        .arg(&c_path)
+        .arg("-o")
+        .arg(exe)
+        .args(&config.flags)
+        .output()
+        .map_err(|e| format!("Failed to run C compiler '{}': {}", compiler, e))?;
+
+    //The generated C carries #line directives, so these already point at the .sco file
+    eprint!("{}", String::from_utf8_lossy(&output.stderr));
+
+    if output.status.success() {
+        Ok(())
+    } else {
+        Err(format!("C compiler '{}' failed ({})", compiler, output.status))
+    }
+}